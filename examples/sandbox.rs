@@ -0,0 +1,46 @@
+// Sandboxes only exist on Linux, since they're built on namespaces.
+#[cfg(target_os = "linux")]
+fn main() -> bossy::Result<()> {
+    use bossy::{Command, Sandbox};
+    use simple_logger::SimpleLogger;
+
+    SimpleLogger::new().init().unwrap();
+
+    // Every sandbox gets its own user namespace, which is what lets this work
+    // without root (as long as your distro allows unprivileged user
+    // namespaces; if it doesn't, you'll get `Cause::SandboxUnavailable`).
+    let sandbox = Sandbox::new()
+        // Not even `localhost` works in here.
+        .with_no_network()
+        // The command is PID 1, and gets a `/proc` that only shows its own
+        // descendants.
+        .with_pid_namespace()
+        // A bind mount of a directory onto itself is a convenient way to
+        // make it read-only.
+        .with_read_only_bind("src", "src")
+        // Anything written to `/tmp` disappears with the sandbox.
+        .with_private_tmp();
+
+    let output = Command::impure("sh")
+        .with_args([
+            "-c",
+            "echo \"PID: $$\"; \
+             echo \"processes: $(ls /proc | grep -c '^[0-9]')\"; \
+             echo \"tmp: $(ls -A /tmp | wc -l) entries\"; \
+             touch src/nope 2>/dev/null || echo 'src is read-only'; \
+             ping -c 1 -W 1 127.0.0.1 >/dev/null 2>&1 || echo 'network is unreachable'",
+        ])
+        .with_sandbox(sandbox)
+        .run_and_wait_for_output()?;
+    println!(
+        "{}",
+        output.stdout_str().expect("output contained invalid utf-8")
+    );
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    println!("sandboxes are only supported on Linux");
+}
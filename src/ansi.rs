@@ -0,0 +1,101 @@
+/// Whether or not a command should use color, for
+/// [`Command::set_color`](crate::Command::set_color).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Let the command decide, which usually means using color only when
+    /// it's talking to a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+/// What [`ColorMode`] does to a single env var.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum EnvAction {
+    Set(&'static str),
+    Remove,
+    /// Use whatever the parent process has, or nothing if it doesn't have it.
+    Inherit,
+    /// Use whatever the parent process has, unless it doesn't have it or has
+    /// it set to `dumb`, in which case use this instead.
+    InheritOr(&'static str),
+}
+
+impl ColorMode {
+    /// The env vars to change to get this behavior out of as many tools as
+    /// possible.
+    pub(crate) fn env_vars(self) -> [(&'static str, EnvAction); 4] {
+        use EnvAction::*;
+        match self {
+            Self::Auto => [
+                ("NO_COLOR", Inherit),
+                ("CLICOLOR_FORCE", Inherit),
+                ("CARGO_TERM_COLOR", Inherit),
+                ("TERM", Inherit),
+            ],
+            Self::Always => [
+                ("NO_COLOR", Remove),
+                ("CLICOLOR_FORCE", Set("1")),
+                ("CARGO_TERM_COLOR", Set("always")),
+                // Plenty of tools won't use color on a dumb terminal, no
+                // matter what else we say.
+                ("TERM", InheritOr("xterm-256color")),
+            ],
+            Self::Never => [
+                ("NO_COLOR", Set("1")),
+                ("CLICOLOR_FORCE", Remove),
+                ("CARGO_TERM_COLOR", Set("never")),
+                ("TERM", Set("dumb")),
+            ],
+        }
+    }
+}
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+#[derive(Clone, Copy)]
+enum State {
+    Text,
+    Escape,
+    // Intermediate bytes of a two-byte-ish escape, like `ESC ( B`.
+    EscapeIntermediate,
+    Csi,
+    // OSC, DCS, SOS, PM, and APC strings, which all end with ST (`ESC \`),
+    // though OSC is also commonly ended with BEL.
+    String,
+    StringEscape,
+}
+
+/// Removes ANSI/VT escape sequences (colors, cursor movement, window titles,
+/// etc.) from `bytes`, leaving everything else alone.
+pub(crate) fn strip(bytes: &[u8]) -> Vec<u8> {
+    let mut stripped = Vec::with_capacity(bytes.len());
+    let mut state = State::Text;
+    for &byte in bytes {
+        state = match (state, byte) {
+            (State::Text, ESC) => State::Escape,
+            (State::Text, _) => {
+                stripped.push(byte);
+                State::Text
+            }
+            (State::Escape, b'[') => State::Csi,
+            (State::Escape, b']') | (State::Escape, b'P') | (State::Escape, b'X') => State::String,
+            (State::Escape, b'^') | (State::Escape, b'_') => State::String,
+            (State::Escape, 0x20..=0x2f) => State::EscapeIntermediate,
+            (State::Escape, _) => State::Text,
+            (State::EscapeIntermediate, 0x20..=0x2f) => State::EscapeIntermediate,
+            (State::EscapeIntermediate, _) => State::Text,
+            // Parameter and intermediate bytes keep the sequence going, and
+            // anything else ends it.
+            (State::Csi, 0x20..=0x3f) => State::Csi,
+            (State::Csi, _) => State::Text,
+            (State::String, BEL) => State::Text,
+            (State::String, ESC) => State::StringEscape,
+            (State::String, _) => State::String,
+            (State::StringEscape, b'\\') => State::Text,
+            (State::StringEscape, _) => State::String,
+        };
+    }
+    stripped
+}
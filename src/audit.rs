@@ -0,0 +1,377 @@
+use crate::{redact::Redactor, Command, Error, ExitStatus, Output};
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+static AUDIT_LOG: RwLock<Option<Arc<AuditLog>>> = RwLock::new(None);
+
+/// Start (or with `None`, stop) recording every command run to an
+/// [`AuditLog`].
+pub fn set_audit_log(log: Option<AuditLog>) {
+    *AUDIT_LOG.write().unwrap_or_else(PoisonError::into_inner) = log.map(Arc::new);
+}
+
+/// How much of a command's captured output to include in its audit record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditOutput {
+    /// Don't include output at all.
+    None,
+    /// Include the length and an FNV-1a hash of each stream.
+    Hash,
+    /// Include up to this many bytes of each stream, as lossy UTF-8.
+    Truncated(usize),
+}
+
+/// A durable record of every command that ran, written as one JSON object per
+/// line. The `env` field says what the command's environment added, changed,
+/// and removed compared to ours.
+///
+/// A record is written once the command is waited on or leaked. If its
+/// [`Handle`](crate::Handle) is dropped without either, the record is written
+/// then, with an error saying so.
+pub struct AuditLog {
+    writer: Mutex<Box<dyn Write + Send + Sync>>,
+    output: AuditOutput,
+}
+
+impl std::fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("output", &self.output)
+            .finish()
+    }
+}
+
+impl AuditLog {
+    pub fn new(writer: impl Write + Send + Sync + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+            output: AuditOutput::None,
+        }
+    }
+
+    /// Append to the file at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(Self::new)
+    }
+
+    pub fn with_output(mut self, output: AuditOutput) -> Self {
+        self.output = output;
+        self
+    }
+
+    fn write_line(&self, line: &str) {
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Err(err) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+            log::error!("failed to write to audit log: {}", err);
+        }
+    }
+}
+
+fn json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn json_keys(out: &mut String, keys: &[OsString]) {
+    out.push('[');
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        json_str(out, &key.to_string_lossy());
+    }
+    out.push(']');
+}
+
+fn json_vars(out: &mut String, vars: &[(&OsStr, &OsStr)], redact: impl Fn(&str) -> String) {
+    out.push('{');
+    for (i, (key, val)) in vars.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        json_str(out, &key.to_string_lossy());
+        out.push(':');
+        json_str(out, &redact(&val.to_string_lossy()));
+    }
+    out.push('}');
+}
+
+/// Writes how the command's environment differs from ours.
+fn json_env(out: &mut String, command: &Command) {
+    let redact = |s: &str| command.redact(s).into_owned();
+    let mut added = Vec::new();
+    let mut changed = Vec::new();
+    let mut removed = Vec::new();
+    for (key, val) in command.inner.get_envs() {
+        match (val, env::var_os(key)) {
+            (Some(val), None) => added.push((key, val)),
+            (Some(val), Some(old)) if val != old => changed.push((key, val)),
+            (None, Some(_)) if !command.pure => removed.push(key.to_owned()),
+            _ => (),
+        }
+    }
+    if command.pure {
+        // Everything we have that wasn't set again is gone.
+        removed.extend(env::vars_os().map(|(key, _)| key).filter(|key| {
+            !command
+                .inner
+                .get_envs()
+                .any(|(set, val)| set == key && val.is_some())
+        }));
+        removed.sort();
+    }
+    write!(out, "{{\"cleared\":{},\"added\":", command.pure).unwrap();
+    json_vars(out, &added, redact);
+    out.push_str(",\"changed\":");
+    json_vars(out, &changed, redact);
+    out.push_str(",\"removed\":");
+    json_keys(out, &removed);
+    out.push('}');
+}
+
+fn json_opt<T: std::fmt::Display>(out: &mut String, value: Option<T>) {
+    match value {
+        Some(value) => write!(out, "{}", value).unwrap(),
+        None => out.push_str("null"),
+    }
+}
+
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[derive(Debug)]
+struct Record {
+    log: Arc<AuditLog>,
+    redactor: Arc<Redactor>,
+    // Everything known before spawning, already rendered as JSON fields.
+    prefix: String,
+    start: Instant,
+    pid: Option<u32>,
+}
+
+/// The audit record for a single run of a command, if an audit log is set.
+#[derive(Debug)]
+pub(crate) struct Audit(Option<Record>);
+
+impl Audit {
+    pub fn none() -> Self {
+        Self(None)
+    }
+
+    pub fn new(command: &Command) -> Self {
+        let log = match AUDIT_LOG
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+        {
+            Some(log) => Arc::clone(log),
+            None => return Self::none(),
+        };
+        let redact = |s: &str| command.redact(s).into_owned();
+        let mut prefix = String::from("{\"program\":");
+        let (program, args) = command
+            .components
+            .split_first()
+            .expect("developer error: command has no program");
+        json_str(&mut prefix, &redact(program));
+        prefix.push_str(",\"args\":[");
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                prefix.push(',');
+            }
+            json_str(&mut prefix, &redact(arg));
+        }
+        prefix.push_str("],\"cwd\":");
+        let cwd = command
+            .inner
+            .get_current_dir()
+            .map(ToOwned::to_owned)
+            .or_else(|| env::current_dir().ok());
+        match cwd {
+            Some(cwd) => json_str(&mut prefix, &cwd.to_string_lossy()),
+            None => prefix.push_str("null"),
+        }
+        prefix.push_str(",\"env\":");
+        json_env(&mut prefix, command);
+        let start_unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        write!(prefix, ",\"start_unix_ms\":{}", start_unix_ms).unwrap();
+        Self(Some(Record {
+            log,
+            redactor: Arc::clone(&command.redactor),
+            prefix,
+            start: Instant::now(),
+            pid: None,
+        }))
+    }
+
+    pub fn spawned(&mut self, pid: u32) {
+        if let Some(record) = self.0.as_mut() {
+            record.pid = Some(pid);
+        }
+    }
+
+    fn write_output(record: &Record, out: &mut String, name: &str, bytes: &[u8]) {
+        write!(out, ",\"{}\":{{\"len\":{}", name, bytes.len()).unwrap();
+        match record.log.output {
+            AuditOutput::None => unreachable!(),
+            AuditOutput::Hash => write!(out, ",\"fnv1a64\":\"{:016x}\"", fnv1a64(bytes)).unwrap(),
+            AuditOutput::Truncated(max) => {
+                let head = String::from_utf8_lossy(&bytes[..bytes.len().min(max)]);
+                out.push_str(",\"head\":");
+                json_str(out, &record.redactor.redact(&head));
+                write!(out, ",\"truncated\":{}", bytes.len() > max).unwrap();
+            }
+        }
+        out.push('}');
+    }
+
+    fn finish(
+        &self,
+        status: Option<ExitStatus>,
+        output: Option<&Output>,
+        error: Option<&str>,
+        detached: bool,
+    ) {
+        let record = match self.0.as_ref() {
+            Some(record) => record,
+            None => return,
+        };
+        let mut line = record.prefix.clone();
+        write!(
+            line,
+            ",\"duration_ms\":{},\"pid\":",
+            record.start.elapsed().as_millis()
+        )
+        .unwrap();
+        json_opt(&mut line, record.pid);
+        line.push_str(",\"exit_code\":");
+        json_opt(&mut line, status.and_then(|status| status.code()));
+        line.push_str(",\"signal\":");
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt as _;
+            json_opt(&mut line, status.and_then(|status| status.signal()));
+        }
+        #[cfg(not(unix))]
+        line.push_str("null");
+        line.push_str(",\"success\":");
+        json_opt(&mut line, status.map(|status| status.success()));
+        write!(line, ",\"detached\":{},\"error\":", detached).unwrap();
+        match error {
+            Some(error) => json_str(&mut line, error),
+            None => line.push_str("null"),
+        }
+        if let Some(output) = output.filter(|_| record.log.output != AuditOutput::None) {
+            Self::write_output(record, &mut line, "stdout", output.stdout());
+            Self::write_output(record, &mut line, "stderr", output.stderr());
+        }
+        line.push('}');
+        record.log.write_line(&line);
+    }
+
+    pub fn spawn_failed(&self, err: &impl std::fmt::Display) {
+        self.finish(None, None, Some(&err.to_string()), false);
+    }
+
+    pub fn dropped(&self) {
+        self.finish(
+            None,
+            None,
+            Some("the handle was dropped without being waited on"),
+            false,
+        );
+    }
+
+    pub fn detached(&self) {
+        self.finish(None, None, None, true);
+    }
+
+    pub fn waited(&self, result: &Result<ExitStatus, Error>) {
+        match result {
+            Ok(status) => self.finish(Some(*status), None, None, false),
+            Err(err) => self.finish(err.status(), None, Some(&err.to_string()), false),
+        }
+    }
+
+    pub fn waited_for_output(&self, result: &Result<Output, Error>) {
+        match result {
+            Ok(output) => self.finish(Some(output.status()), Some(output), None, false),
+            Err(err) => self.finish(err.status(), err.output(), Some(&err.to_string()), false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn escaped(s: &str) -> String {
+        let mut out = String::new();
+        json_str(&mut out, s);
+        out
+    }
+
+    #[test]
+    fn quotes_and_backslashes() {
+        assert_eq!(escaped(r#"say "hi" \o/"#), r#""say \"hi\" \\o/""#);
+    }
+
+    #[test]
+    fn control_characters() {
+        assert_eq!(escaped("a\nb\r\tc"), r#""a\nb\r\tc""#);
+        assert_eq!(
+            escaped("\u{0}\u{1b}[0m\u{7f}"),
+            "\"\\u0000\\u001b[0m\u{7f}\""
+        );
+    }
+
+    #[test]
+    fn everything_round_trips() {
+        let s = (0..=0x7f)
+            .filter_map(char::from_u32)
+            .chain("é🦀\u{2028}".chars())
+            .collect::<String>();
+        let parsed: String = serde_json::from_str(&escaped(&s)).unwrap();
+        assert_eq!(parsed, s);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_is_replaced() {
+        use std::os::unix::ffi::OsStrExt as _;
+        let key = OsStr::from_bytes(b"KEY\xff");
+        let mut out = String::new();
+        json_keys(&mut out, &[key.to_owned()]);
+        let parsed: Vec<String> = serde_json::from_str(&out).unwrap();
+        assert_eq!(parsed, ["KEY\u{fffd}"]);
+    }
+}
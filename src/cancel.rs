@@ -0,0 +1,58 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+#[derive(Debug, Default)]
+struct Shared {
+    cancelled: AtomicBool,
+    grace: Mutex<Option<Duration>>,
+}
+
+/// Cancels commands from another thread. Attach it to a
+/// [`Command`](crate::Command) or [`Handle`](crate::Handle), and once it's
+/// cancelled, the child is stopped and waiting on it fails with
+/// [`Cause::Cancelled`](crate::Cause::Cancelled).
+///
+/// Clones share everything, so you can keep one and hand out the rest.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<Shared>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// By default, cancelled commands are killed right away. With a grace
+    /// period, they're asked to exit first (with `SIGTERM` on Unix), and only
+    /// killed if they're still running once it's up. Elsewhere, there's no
+    /// asking, so this does nothing.
+    pub fn set_grace_period(&mut self, grace: Duration) -> &mut Self {
+        *self.0.grace.lock().unwrap_or_else(PoisonError::into_inner) = Some(grace);
+        self
+    }
+
+    pub fn with_grace_period(mut self, grace: Duration) -> Self {
+        self.set_grace_period(grace);
+        self
+    }
+
+    pub(crate) fn grace_period(&self) -> Option<Duration> {
+        *self.0.grace.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Cancel every command this is attached to. This doesn't block, and
+    /// there's no taking it back.
+    pub fn cancel(&self) {
+        if !self.0.cancelled.swap(true, Ordering::SeqCst) {
+            log::info!("cancellation requested");
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,231 @@
+use crate::{dry_run, CancellationToken, Cause, Command, Error, Output};
+use std::{
+    fmt::{self, Display},
+    sync::Mutex,
+    thread,
+};
+
+/// What a [`CommandSet`] should do when one of its commands fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureMode {
+    /// Kill everything that's still running and skip everything that hasn't
+    /// started yet.
+    FailFast,
+    /// Run everything regardless.
+    KeepGoing,
+}
+
+/// Every failure from running a [`CommandSet`], along with the index of the
+/// command that failed. With [`FailureMode::FailFast`], the commands that were
+/// skipped or cancelled because of a failure are kept separate, so the
+/// failure itself is easy to find.
+#[derive(Debug)]
+pub struct Failures {
+    total: usize,
+    errors: Vec<(usize, Error)>,
+    aborted: Vec<(usize, Error)>,
+}
+
+impl Display for Failures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} commands failed:",
+            self.errors.len(),
+            self.total
+        )?;
+        for (index, err) in &self.errors {
+            write!(f, "\n  [{}] {}", index, err)?;
+        }
+        if !self.aborted.is_empty() {
+            let indices = self
+                .aborted
+                .iter()
+                .map(|(index, _)| index.to_string())
+                .collect::<Vec<_>>();
+            write!(
+                f,
+                "\n{} more were skipped or cancelled as a result: [{}]",
+                self.aborted.len(),
+                indices.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Failures {}
+
+impl Failures {
+    /// The commands that actually failed.
+    pub fn errors(&self) -> &[(usize, Error)] {
+        &self.errors
+    }
+
+    pub fn into_errors(self) -> Vec<(usize, Error)> {
+        self.errors
+    }
+
+    /// The commands that were skipped or cancelled because something else
+    /// failed first.
+    pub fn aborted(&self) -> &[(usize, Error)] {
+        &self.aborted
+    }
+}
+
+/// Run a bunch of commands concurrently, without running more than
+/// `max_parallel` at once. Output is always captured, just like with
+/// [`Command::run_and_wait_for_output`].
+#[derive(Debug)]
+pub struct CommandSet {
+    commands: Vec<Command>,
+    max_parallel: usize,
+    mode: FailureMode,
+}
+
+impl CommandSet {
+    /// A `max_parallel` of `0` is treated as `1`.
+    pub fn new(max_parallel: usize) -> Self {
+        Self {
+            commands: Vec::new(),
+            max_parallel: max_parallel.max(1),
+            mode: FailureMode::KeepGoing,
+        }
+    }
+
+    pub fn add_command(&mut self, command: Command) -> &mut Self {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn with_command(mut self, command: Command) -> Self {
+        self.add_command(command);
+        self
+    }
+
+    pub fn add_commands(&mut self, commands: impl IntoIterator<Item = Command>) -> &mut Self {
+        self.commands.extend(commands);
+        self
+    }
+
+    pub fn with_commands(mut self, commands: impl IntoIterator<Item = Command>) -> Self {
+        self.add_commands(commands);
+        self
+    }
+
+    pub fn set_failure_mode(&mut self, mode: FailureMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn with_failure_mode(mut self, mode: FailureMode) -> Self {
+        self.set_failure_mode(mode);
+        self
+    }
+
+    /// Run everything and block until it's all done. Results are in the same
+    /// order as the commands were added.
+    pub fn run(self) -> Vec<crate::Result<Output>> {
+        self.run_tracked()
+            .into_iter()
+            .map(|(result, _)| result)
+            .collect()
+    }
+
+    /// The same as `run`, but also says whether each command was skipped or
+    /// cancelled because a different one failed.
+    fn run_tracked(self) -> Vec<(crate::Result<Output>, bool)> {
+        let Self {
+            commands,
+            max_parallel,
+            mode,
+        } = self;
+        log::info!(
+            "running {} commands, up to {} at a time",
+            commands.len(),
+            max_parallel
+        );
+        let workers = max_parallel.min(commands.len());
+        let results = commands
+            .iter()
+            .map(|_| Mutex::new(None))
+            .collect::<Vec<_>>();
+        let queue = Mutex::new(commands.into_iter().enumerate());
+        let abort = CancellationToken::new();
+        // Dry-run scopes are per-thread, so the workers need their own.
+        let is_dry_run = dry_run::is_dry_run();
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    let _dry_run = dry_run::scoped_dry_run(is_dry_run);
+                    loop {
+                        let (index, mut command) = match queue.lock().unwrap().next() {
+                            Some(next) => next,
+                            None => break,
+                        };
+                        let result = if abort.is_cancelled() {
+                            log::info!("skipping command {:?}", command.display());
+                            Err(Error::new(command.display().to_owned(), Cause::Skipped))
+                        } else {
+                            command.run_and_wait_for_output_cancellable(&abort)
+                        };
+                        // If we weren't the first to fail, then this is
+                        // probably because of whoever was.
+                        let aborted = abort.is_cancelled()
+                            && matches!(
+                                result.as_ref().map_err(Error::cause),
+                                Err(Cause::Skipped | Cause::Cancelled(_))
+                            );
+                        if result.is_err() && mode == FailureMode::FailFast {
+                            abort.cancel();
+                        }
+                        *results[index].lock().unwrap() = Some((result, aborted));
+                    }
+                });
+            }
+        });
+        results
+            .into_iter()
+            .map(|result| {
+                result
+                    .into_inner()
+                    .unwrap()
+                    .expect("developer error: command in set never ran")
+            })
+            .collect()
+    }
+
+    /// The same as `run`, but gives you every failure at once if anything
+    /// failed.
+    pub fn run_and_check(self) -> Result<Vec<Output>, Failures> {
+        let results = self.run_tracked();
+        let total = results.len();
+        let mut outputs = Vec::with_capacity(total);
+        let mut errors = Vec::new();
+        let mut aborted = Vec::new();
+        for (index, result) in results.into_iter().enumerate() {
+            match result {
+                (Ok(output), _) => outputs.push(output),
+                (Err(err), false) => errors.push((index, err)),
+                (Err(err), true) => aborted.push((index, err)),
+            }
+        }
+        if errors.is_empty() && aborted.is_empty() {
+            Ok(outputs)
+        } else {
+            Err(Failures {
+                total,
+                errors,
+                aborted,
+            })
+        }
+    }
+}
+
+/// Shorthand for running a [`CommandSet`] with [`FailureMode::KeepGoing`].
+pub fn run_all(
+    commands: impl IntoIterator<Item = Command>,
+    max_parallel: usize,
+) -> Vec<crate::Result<Output>> {
+    CommandSet::new(max_parallel).with_commands(commands).run()
+}
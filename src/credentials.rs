@@ -0,0 +1,151 @@
+use crate::{pre_exec::Step, Cause};
+use std::{ffi::CString, io, ptr};
+
+/// Who a command runs as, and with what umask.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Credentials {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub groups: Option<Vec<u32>>,
+    pub umask: Option<u32>,
+}
+
+impl Credentials {
+    /// This is meant to run in the child between `fork` and `exec`, so it
+    /// can't allocate. Groups have to be set first, since we won't have
+    /// permission to once we're no longer root.
+    pub fn apply(&self) -> io::Result<()> {
+        unsafe {
+            if let Some(umask) = self.umask {
+                libc::umask(umask as libc::mode_t);
+            }
+            if let Some(groups) = &self.groups {
+                Step::SetGroups.check(libc::setgroups(
+                    groups.len() as _,
+                    groups.as_ptr() as *const _,
+                ))?;
+            } else if self.uid.is_some() && libc::geteuid() == 0 {
+                // Otherwise, we'd keep all of root's supplementary groups.
+                Step::SetGroups.check(libc::setgroups(0, ptr::null()))?;
+            }
+            if let Some(gid) = self.gid {
+                Step::SetGid.check(libc::setgid(gid as libc::gid_t))?;
+            }
+            if let Some(uid) = self.uid {
+                Step::SetUid.check(libc::setuid(uid as libc::uid_t))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Refuses to change the user ID without the group ID when our group is
+    /// root's, since the command would still be in root's group.
+    pub fn check(&self) -> Result<(), Cause> {
+        if let (Some(uid), None) = (self.uid, self.gid) {
+            if unsafe { libc::getegid() } == 0 {
+                return Err(Cause::SetUidFailed {
+                    uid,
+                    source: io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "a group ID has to be set too, or the command would stay in root's group",
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// A more helpful [`Cause`] for a failed step, if it was one of ours.
+    pub fn explain(&self, step: Step, source: io::Error) -> Result<Cause, io::Error> {
+        match step {
+            Step::SetGroups => Ok(Cause::SetGroupsFailed {
+                groups: self.groups.clone().unwrap_or_default(),
+                source,
+            }),
+            Step::SetGid => Ok(Cause::SetGidFailed {
+                gid: self.gid.unwrap_or_default(),
+                source,
+            }),
+            Step::SetUid => Ok(Cause::SetUidFailed {
+                uid: self.uid.unwrap_or_default(),
+                source,
+            }),
+            _ => Err(source),
+        }
+    }
+}
+
+/// A user from the password database, along with all of their groups.
+#[derive(Debug)]
+pub(crate) struct User {
+    pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>,
+    pub home: String,
+}
+
+/// Looks up `name` with `getpwnam_r` and `getgrouplist`.
+pub(crate) fn lookup_user(name: &str) -> Result<User, Cause> {
+    let lookup_failed = |source| Cause::UserLookupFailed {
+        user: name.to_owned(),
+        source,
+    };
+    let c_name = CString::new(name).map_err(|err| lookup_failed(err.into()))?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut passwd = unsafe { std::mem::zeroed::<libc::passwd>() };
+    let mut result = ptr::null_mut();
+    loop {
+        let ret = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match ret {
+            0 => break,
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            err => return Err(lookup_failed(io::Error::from_raw_os_error(err))),
+        }
+    }
+    if result.is_null() {
+        return Err(Cause::UserNotFound {
+            user: name.to_owned(),
+        });
+    }
+    let home = unsafe { std::ffi::CStr::from_ptr(passwd.pw_dir) }
+        .to_string_lossy()
+        .into_owned();
+    let mut groups = vec![0 as libc::gid_t; 32];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let ret = unsafe {
+            libc::getgrouplist(
+                c_name.as_ptr(),
+                passwd.pw_gid as _,
+                groups.as_mut_ptr() as *mut _,
+                &mut count,
+            )
+        };
+        if ret != -1 {
+            groups.truncate(count as usize);
+            break;
+        }
+        // Linux tells us how many groups there are, but not everything does.
+        let len = (count as usize).max(groups.len() * 2);
+        if len > 1 << 16 {
+            return Err(lookup_failed(io::Error::other(
+                "user is in too many groups",
+            )));
+        }
+        groups.resize(len, 0);
+    }
+    Ok(User {
+        uid: passwd.pw_uid as u32,
+        gid: passwd.pw_gid as u32,
+        groups,
+        home,
+    })
+}
@@ -0,0 +1,353 @@
+use crate::{Cause, DetachOptions};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read as _, Seek as _, Write as _},
+    os::unix::{
+        fs::OpenOptionsExt as _,
+        io::{AsRawFd as _, RawFd},
+    },
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Where a [`Daemon`] keeps its pidfile and output, for
+/// [`Command::run_as_daemon`](crate::Command::run_as_daemon). By default,
+/// there's no pidfile, and any output the command wasn't already set up to
+/// send somewhere is discarded.
+#[derive(Clone, Debug, Default)]
+pub struct DaemonOptions {
+    pidfile: Option<PathBuf>,
+    detach: DetachOptions,
+}
+
+impl DaemonOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write the daemon's pid to `path`, which stays locked for as long as
+    /// the daemon's running. If the pidfile is already locked, then the
+    /// daemon isn't started.
+    ///
+    /// The lock is held by a file descriptor the daemon inherits, so daemons
+    /// that close every file descriptor when they start won't be detected as
+    /// running.
+    pub fn set_pidfile(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.pidfile = Some(path.into());
+        self
+    }
+
+    pub fn with_pidfile(mut self, path: impl Into<PathBuf>) -> Self {
+        self.set_pidfile(path);
+        self
+    }
+
+    /// Append the daemon's stdout to `path`, creating it if needed.
+    pub fn set_stdout_log(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.detach.set_stdout_log(path);
+        self
+    }
+
+    pub fn with_stdout_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.set_stdout_log(path);
+        self
+    }
+
+    /// Append the daemon's stderr to `path`, creating it if needed.
+    pub fn set_stderr_log(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.detach.set_stderr_log(path);
+        self
+    }
+
+    pub fn with_stderr_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.set_stderr_log(path);
+        self
+    }
+
+    /// Append both stdout and stderr to `path`.
+    pub fn set_log(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.detach.set_log(path);
+        self
+    }
+
+    pub fn with_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.set_log(path);
+        self
+    }
+
+    pub(crate) fn pidfile(&self) -> Option<&Path> {
+        self.pidfile.as_deref()
+    }
+
+    pub(crate) fn detach(&self) -> &DetachOptions {
+        &self.detach
+    }
+}
+
+fn flock(file: &File, operation: libc::c_int) -> io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+        Ok(true)
+    } else {
+        let err = io::Error::last_os_error();
+        if err.kind() == io::ErrorKind::WouldBlock {
+            Ok(false)
+        } else {
+            Err(err)
+        }
+    }
+}
+
+fn read_pid(file: &mut File) -> io::Result<Option<u32>> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(contents.trim().parse().ok())
+}
+
+/// A pidfile we hold the lock on, until the daemon inherits it.
+#[derive(Debug)]
+pub(crate) struct Pidfile {
+    path: PathBuf,
+    file: File,
+}
+
+impl Pidfile {
+    pub fn lock(path: &Path) -> Result<Self, Cause> {
+        let failed = |source| Cause::PidfileFailed {
+            path: path.to_owned(),
+            source,
+        };
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o644)
+            .open(path)
+            .map_err(failed)?;
+        if !flock(&file, libc::LOCK_EX).map_err(failed)? {
+            return Err(Cause::DaemonAlreadyRunning {
+                pidfile: path.to_owned(),
+                pid: read_pid(&mut file).ok().flatten(),
+            });
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+        })
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    pub fn write(mut self, pid: u32) -> Result<PathBuf, Cause> {
+        let result = (|| {
+            self.file.set_len(0)?;
+            self.file.rewind()?;
+            writeln!(self.file, "{}", pid)
+        })();
+        result.map_err(|source| Cause::PidfileFailed {
+            path: self.path.clone(),
+            source,
+        })?;
+        Ok(self.path)
+    }
+}
+
+/// What the child needs to detach, which is shared with a `pre_exec` hook.
+#[derive(Debug)]
+pub(crate) struct Detach {
+    // Where the intermediate process sends the daemon's pid.
+    pub pid_pipe: RawFd,
+    // This has to survive `exec` for the daemon to keep holding the lock.
+    pub pidfile: Option<RawFd>,
+}
+
+impl Detach {
+    /// This is meant to run in the child between `fork` and `exec`, so it
+    /// can't allocate. Forks again so that the daemon isn't our child, and
+    /// becomes the leader of a new session so it has no controlling
+    /// terminal.
+    ///
+    /// The daemon inherits the pipe std uses to report `exec` failures, so
+    /// spawning still fails if the daemon can't start, and doesn't succeed
+    /// until it has.
+    pub fn apply(&self) -> io::Result<()> {
+        unsafe {
+            match libc::fork() {
+                -1 => Err(io::Error::last_os_error()),
+                0 => {
+                    if libc::setsid() == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    if let Some(pidfile) = self.pidfile {
+                        if libc::fcntl(pidfile, libc::F_SETFD, 0) == -1 {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                }
+                pid => {
+                    let bytes = pid.to_ne_bytes();
+                    libc::write(self.pid_pipe, bytes.as_ptr() as *const _, bytes.len());
+                    libc::_exit(0)
+                }
+            }
+        }
+    }
+}
+
+/// Reads the daemon's pid, once the intermediate process has sent it.
+pub(crate) fn read_daemon_pid(mut pipe: impl io::Read) -> io::Result<u32> {
+    let mut bytes = [0; std::mem::size_of::<libc::pid_t>()];
+    pipe.read_exact(&mut bytes)?;
+    Ok(libc::pid_t::from_ne_bytes(bytes) as u32)
+}
+
+/// A command running in the background, detached from us, from
+/// [`Command::run_as_daemon`](crate::Command::run_as_daemon) or
+/// [`Daemon::from_pidfile`]. Unlike with a [`Handle`](crate::Handle), we
+/// can't wait on it, so we can only ever check in on it.
+#[derive(Debug)]
+pub struct Daemon {
+    pid: u32,
+    pidfile: Option<PathBuf>,
+    // Dry runs never actually start anything.
+    fake: bool,
+}
+
+impl Daemon {
+    pub(crate) fn new(pid: u32, pidfile: Option<PathBuf>) -> Self {
+        Self {
+            pid,
+            pidfile,
+            fake: false,
+        }
+    }
+
+    pub(crate) fn fake(pidfile: Option<PathBuf>) -> Self {
+        Self {
+            pid: 0,
+            pidfile,
+            fake: true,
+        }
+    }
+
+    /// Find the daemon named by the pidfile at `path`, if it's still running.
+    /// Since it's locked for as long as the daemon's running, a stale pidfile
+    /// can't get the wrong process.
+    pub fn from_pidfile(path: impl AsRef<Path>) -> io::Result<Option<Self>> {
+        let path = path.as_ref();
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if flock(&file, libc::LOCK_SH)? {
+            return Ok(None);
+        }
+        Ok(read_pid(&mut file)?.map(|pid| Self::new(pid, Some(path.to_owned()))))
+    }
+
+    /// This is always 0 for dry runs.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn pidfile(&self) -> Option<&Path> {
+        self.pidfile.as_deref()
+    }
+
+    fn signal(&self, signal: libc::c_int) -> io::Result<()> {
+        if unsafe { libc::kill(self.pid as libc::pid_t, signal) } == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn pidfile_locked(&self) -> io::Result<bool> {
+        match &self.pidfile {
+            Some(path) => match File::open(path) {
+                Ok(file) => flock(&file, libc::LOCK_SH).map(|unlocked| !unlocked),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+                Err(err) => Err(err),
+            },
+            None => Ok(true),
+        }
+    }
+
+    /// Whether or not the daemon's still running. With a pidfile, this is
+    /// based on whether the daemon still holds the lock on it, so there's no
+    /// chance of mistaking some other process that got the same pid for it.
+    pub fn is_running(&self) -> bool {
+        if self.fake {
+            return false;
+        }
+        let alive = match self.signal(0) {
+            Ok(()) => !self.is_zombie(),
+            Err(err) => err.raw_os_error() == Some(libc::EPERM),
+        };
+        alive && self.pidfile_locked().unwrap_or(true)
+    }
+
+    // Since the daemon isn't our child, it stays a zombie until init gets
+    // around to reaping it.
+    #[cfg(target_os = "linux")]
+    fn is_zombie(&self) -> bool {
+        fs::read_to_string(format!("/proc/{}/stat", self.pid))
+            .ok()
+            .and_then(|stat| {
+                let (_, state) = stat.rsplit_once(')')?;
+                state.trim_start().chars().next()
+            })
+            == Some('Z')
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn is_zombie(&self) -> bool {
+        false
+    }
+
+    /// Ask the daemon to stop with `SIGTERM`, giving it `grace` to do so
+    /// before killing it with `SIGKILL`. The pidfile is removed once it's
+    /// stopped.
+    pub fn stop(&self, grace: Duration) -> io::Result<()> {
+        if self.is_running() {
+            log::info!("stopping daemon with pid {}", self.pid);
+            self.signal(libc::SIGTERM)?;
+            if !self.wait_for_exit(grace) {
+                log::warn!(
+                    "daemon with pid {} didn't stop within {:?}, so killing it",
+                    self.pid,
+                    grace
+                );
+                self.signal(libc::SIGKILL)?;
+                self.wait_for_exit(Duration::from_secs(5));
+            }
+        }
+        if let Some(path) = &self.pidfile {
+            if !self.fake && !self.pidfile_locked()? {
+                match fs::remove_file(path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn wait_for_exit(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if !self.is_running() {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
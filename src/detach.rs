@@ -0,0 +1,84 @@
+use crate::Cause;
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+};
+
+/// Where a detached command's output goes, for
+/// [`Command::run_and_detach_with`](crate::Command::run_and_detach_with).
+/// Log files take precedence over whatever stdout and stderr were set to on
+/// the command.
+#[derive(Clone, Debug, Default)]
+pub struct DetachOptions {
+    stdout_log: Option<PathBuf>,
+    stderr_log: Option<PathBuf>,
+}
+
+impl DetachOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append the command's stdout to `path`, creating it if needed.
+    pub fn set_stdout_log(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.stdout_log = Some(path.into());
+        self
+    }
+
+    pub fn with_stdout_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.set_stdout_log(path);
+        self
+    }
+
+    /// Append the command's stderr to `path`, creating it if needed.
+    pub fn set_stderr_log(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        self.stderr_log = Some(path.into());
+        self
+    }
+
+    pub fn with_stderr_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.set_stderr_log(path);
+        self
+    }
+
+    /// Append both stdout and stderr to `path`.
+    pub fn set_log(&mut self, path: impl Into<PathBuf>) -> &mut Self {
+        let path = path.into();
+        self.stdout_log = Some(path.clone());
+        self.stderr_log = Some(path);
+        self
+    }
+
+    pub fn with_log(mut self, path: impl Into<PathBuf>) -> Self {
+        self.set_log(path);
+        self
+    }
+
+    /// Opens the log files, returning what stdout and stderr should be.
+    pub(crate) fn open_logs(&self) -> Result<(Option<File>, Option<File>), Cause> {
+        let stdout = self.stdout_log.as_deref().map(open_log).transpose()?;
+        let stderr = match (&stdout, self.stderr_log.as_deref()) {
+            // Sharing the file keeps the two streams from clobbering each
+            // other on platforms without a real append mode.
+            (Some(stdout), Some(path)) if Some(path) == self.stdout_log.as_deref() => {
+                Some(stdout.try_clone().map_err(|source| Cause::LogFileFailed {
+                    path: path.to_owned(),
+                    source,
+                })?)
+            }
+            (_, path) => path.map(open_log).transpose()?,
+        };
+        Ok((stdout, stderr))
+    }
+}
+
+fn open_log(path: &Path) -> Result<File, Cause> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|source| Cause::LogFileFailed {
+            path: path.to_owned(),
+            source,
+        })
+}
@@ -0,0 +1,81 @@
+use crate::ExitStatus;
+use std::{
+    cell::Cell,
+    marker::PhantomData,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static SCOPED: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+/// Turn dry-run mode on or off for the whole process. While it's on, running
+/// a command just logs it (at the `info` level) and pretends it succeeded,
+/// unless the command was marked with
+/// [`Command::set_always_run`](crate::Command::set_always_run).
+pub fn set_dry_run(enabled: bool) {
+    log::info!(
+        "{} dry-run mode",
+        if enabled { "enabling" } else { "disabling" }
+    );
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// Whether or not commands run on this thread are currently dry runs. A
+/// [`DryRunScope`] takes precedence over [`set_dry_run`].
+pub fn is_dry_run() -> bool {
+    SCOPED
+        .with(Cell::get)
+        .unwrap_or_else(|| ENABLED.load(Ordering::SeqCst))
+}
+
+/// Turn dry-run mode on or off for the current thread, until the returned
+/// scope is dropped.
+pub fn scoped_dry_run(enabled: bool) -> DryRunScope {
+    let previous = SCOPED.with(|scoped| scoped.replace(Some(enabled)));
+    DryRunScope {
+        previous,
+        _not_send: PhantomData,
+    }
+}
+
+/// Restores the previous dry-run setting for the current thread when dropped.
+/// This can't be sent to another thread, since it'd restore the setting there
+/// instead.
+#[derive(Debug)]
+#[must_use = "dry-run mode is turned back off as soon as the scope is dropped"]
+pub struct DryRunScope {
+    previous: Option<bool>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for DryRunScope {
+    fn drop(&mut self) {
+        SCOPED.with(|scoped| scoped.set(self.previous));
+    }
+}
+
+pub(crate) fn exit_status(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+        ExitStatus::from_raw(code << 8)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt as _;
+        ExitStatus::from_raw(code as u32)
+    }
+}
+
+/// Quotes a command component so it can be pasted into a POSIX shell.
+pub(crate) fn shell_escape(component: &str) -> String {
+    let is_safe = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%^".contains(c);
+    if !component.is_empty() && component.chars().all(is_safe) {
+        component.to_owned()
+    } else {
+        format!("'{}'", component.replace('\'', r"'\''"))
+    }
+}
@@ -0,0 +1,362 @@
+use std::{collections::HashMap, iter::Peekable, str::Chars};
+
+/// Where and why an env file failed to parse.
+#[derive(Debug)]
+pub(crate) struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+struct Parser<'a, F> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    defined: HashMap<String, String>,
+    lookup: F,
+}
+
+impl<'a, F: Fn(&str) -> Option<String>> Parser<'a, F> {
+    fn error_at<T>(line: usize, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            line,
+            message: message.into(),
+        })
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Self::error_at(self.line, message)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn skip_horizontal_whitespace(&mut self) {
+        while matches!(self.peek(), Some(c) if c != '\n' && c.is_whitespace()) {
+            self.next();
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while !matches!(self.next(), Some('\n') | None) {}
+    }
+
+    /// Consumes the rest of the line after a value, which may only contain
+    /// whitespace and a comment.
+    fn finish_line(&mut self) -> Result<(), ParseError> {
+        self.skip_horizontal_whitespace();
+        match self.peek() {
+            None | Some('\n') | Some('\r') | Some('#') => {
+                self.skip_line();
+                Ok(())
+            }
+            Some(c) => self.error(format!("unexpected {:?} after quoted value", c)),
+        }
+    }
+
+    fn is_key_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_' || c == '.'
+    }
+
+    fn read_key(&mut self) -> String {
+        let mut key = String::new();
+        while let Some(c) = self.peek().filter(|c| Self::is_key_char(*c)) {
+            key.push(c);
+            self.next();
+        }
+        key
+    }
+
+    fn lookup(&self, name: &str) -> String {
+        self.defined
+            .get(name)
+            .cloned()
+            .or_else(|| (self.lookup)(name))
+            .unwrap_or_default()
+    }
+
+    /// Expands `$VAR` or `${VAR}`, assuming the `$` was already consumed.
+    fn expand(&mut self, value: &mut String) -> Result<(), ParseError> {
+        if self.peek() == Some('{') {
+            self.next();
+            let mut name = String::new();
+            loop {
+                // Peeking first, so the error isn't counted on the next line.
+                match self.peek() {
+                    Some('}') => {
+                        self.next();
+                        break;
+                    }
+                    Some('\n') | None => return self.error("unterminated `${`"),
+                    Some(c) => {
+                        name.push(c);
+                        self.next();
+                    }
+                }
+            }
+            value.push_str(&self.lookup(&name));
+        } else {
+            let mut name = String::new();
+            while let Some(c) = self
+                .peek()
+                .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+            {
+                name.push(c);
+                self.next();
+            }
+            if name.is_empty() {
+                value.push('$');
+            } else {
+                value.push_str(&self.lookup(&name));
+            }
+        }
+        Ok(())
+    }
+
+    fn read_single_quoted(&mut self) -> Result<String, ParseError> {
+        let start = self.line;
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(value),
+                Some(c) => value.push(c),
+                None => return Self::error_at(start, "unterminated single-quoted value"),
+            }
+        }
+    }
+
+    fn read_double_quoted(&mut self) -> Result<String, ParseError> {
+        let start = self.line;
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.next() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some(c) => value.push(c),
+                    None => return Self::error_at(start, "unterminated double-quoted value"),
+                },
+                Some('$') => self.expand(&mut value)?,
+                Some(c) => value.push(c),
+                None => return Self::error_at(start, "unterminated double-quoted value"),
+            }
+        }
+    }
+
+    /// `spaced` is whether there was whitespace between the `=` and the
+    /// value, which has already been skipped.
+    fn read_unquoted(&mut self, spaced: bool) -> Result<String, ParseError> {
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None | Some('\n') => break,
+                // A `#` only starts a comment if it's preceded by whitespace,
+                // so that things like `COLOR=#fff` work.
+                Some('#')
+                    if (spaced && value.is_empty()) || value.ends_with(char::is_whitespace) =>
+                {
+                    self.skip_line();
+                    break;
+                }
+                Some('$') => {
+                    self.next();
+                    self.expand(&mut value)?;
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.next();
+                }
+            }
+        }
+        // Leave the newline for the caller, so line numbers stay accurate for
+        // the next entry.
+        Ok(value.trim_end().to_owned())
+    }
+
+    fn parse_entry(&mut self) -> Result<(String, String), ParseError> {
+        let mut key = self.read_key();
+        if key == "export" && matches!(self.peek(), Some(c) if c != '\n' && c.is_whitespace()) {
+            self.skip_horizontal_whitespace();
+            key = self.read_key();
+        }
+        if key.is_empty() {
+            return match self.peek() {
+                Some(c) => self.error(format!("expected a key, but found {:?}", c)),
+                None => self.error("expected a key"),
+            };
+        }
+        self.skip_horizontal_whitespace();
+        if self.peek() != Some('=') {
+            return self.error(format!("expected `=` after key {:?}", key));
+        }
+        self.next();
+        let spaced = matches!(self.peek(), Some(c) if c != '\n' && c.is_whitespace());
+        self.skip_horizontal_whitespace();
+        let value = match self.peek() {
+            Some('\'') => {
+                self.next();
+                let value = self.read_single_quoted()?;
+                self.finish_line()?;
+                value
+            }
+            Some('"') => {
+                self.next();
+                let value = self.read_double_quoted()?;
+                self.finish_line()?;
+                value
+            }
+            _ => self.read_unquoted(spaced)?,
+        };
+        Ok((key, value))
+    }
+}
+
+/// Parses the contents of a dotenv-style file. `${VAR}` and `$VAR` are expanded
+/// using keys defined earlier in the file, falling back to `lookup`; unset
+/// vars expand to nothing.
+pub(crate) fn parse(
+    contents: &str,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<(String, String)>, ParseError> {
+    let mut parser = Parser {
+        chars: contents.chars().peekable(),
+        line: 1,
+        defined: HashMap::new(),
+        lookup,
+    };
+    let mut vars = Vec::new();
+    loop {
+        while matches!(parser.peek(), Some(c) if c.is_whitespace()) {
+            parser.next();
+        }
+        match parser.peek() {
+            None => break,
+            Some('#') => parser.skip_line(),
+            Some(_) => {
+                let (key, value) = parser.parse_entry()?;
+                parser.defined.insert(key.clone(), value.clone());
+                vars.push((key, value));
+            }
+        }
+    }
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_with(
+        contents: &str,
+        env: &[(&str, &str)],
+    ) -> Result<Vec<(String, String)>, ParseError> {
+        parse(contents, |name| {
+            env.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, val)| (*val).to_owned())
+        })
+    }
+
+    fn parse_ok(contents: &str) -> Vec<(String, String)> {
+        parse_with(contents, &[]).unwrap()
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, val)| ((*key).to_owned(), (*val).to_owned()))
+            .collect()
+    }
+
+    fn error_line(contents: &str) -> usize {
+        parse_with(contents, &[]).unwrap_err().line
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            parse_ok("# leading\nA=1 # trailing\n  # indented\nB=2\n"),
+            vars(&[("A", "1"), ("B", "2")])
+        );
+        assert_eq!(parse_ok("A= # nothing here\n"), vars(&[("A", "")]));
+        assert_eq!(parse_ok("A=#fff\n"), vars(&[("A", "#fff")]));
+        assert_eq!(parse_ok("A=a#b\n"), vars(&[("A", "a#b")]));
+        assert_eq!(parse_ok("A='1' # after quotes\n"), vars(&[("A", "1")]));
+    }
+
+    #[test]
+    fn single_quotes_are_literal() {
+        assert_eq!(
+            parse_ok("A='$HOME \\n # \"x\"'\nB='two\nlines'\n"),
+            vars(&[("A", "$HOME \\n # \"x\""), ("B", "two\nlines")])
+        );
+    }
+
+    #[test]
+    fn double_quotes_have_escapes() {
+        assert_eq!(
+            parse_ok(r#"A="tab\there\nquote\" backslash\\ dollar\$ # not a comment""#),
+            vars(&[(
+                "A",
+                "tab\there\nquote\" backslash\\ dollar$ # not a comment"
+            )])
+        );
+    }
+
+    #[test]
+    fn export_is_ignored() {
+        assert_eq!(
+            parse_ok("export A=1\nexport  B='2'\nexport=3\n"),
+            vars(&[("A", "1"), ("B", "2"), ("export", "3")])
+        );
+    }
+
+    #[test]
+    fn expansion() {
+        let env = [("FROM_ENV", "env"), ("A", "shadowed")];
+        assert_eq!(
+            parse_with(
+                "A=a\nB=$A-${A}\nC=\"$FROM_ENV/${FROM_ENV}\"\nD='$A'\nE=$UNSET.\nF=$ $\n",
+                &env
+            )
+            .unwrap(),
+            vars(&[
+                ("A", "a"),
+                ("B", "a-a"),
+                ("C", "env/env"),
+                ("D", "$A"),
+                ("E", "."),
+                ("F", "$ $"),
+            ])
+        );
+    }
+
+    #[test]
+    fn crlf() {
+        assert_eq!(
+            parse_ok("# comment\r\nA=1\r\nB='2'\r\nC=\"3\" # comment\r\n\r\nD=4"),
+            vars(&[("A", "1"), ("B", "2"), ("C", "3"), ("D", "4")])
+        );
+    }
+
+    #[test]
+    fn error_lines() {
+        assert_eq!(error_line("A=1\n\nB 2\n"), 3);
+        assert_eq!(error_line("A=1\n=2\n"), 2);
+        assert_eq!(error_line("A='1' 2\n"), 1);
+        assert_eq!(error_line("A=${B\nC=1\n"), 1);
+        assert_eq!(error_line("A=1\nB='never\nclosed\nat all\n"), 2);
+        assert_eq!(error_line("A=1\n\nB=\"never\nclosed\n"), 3);
+        // The entry after a multi-line value is counted from the right line.
+        assert_eq!(error_line("A='x\ny'\nB\n"), 3);
+    }
+}
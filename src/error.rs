@@ -1,21 +1,152 @@
-use crate::{ExitStatus, Handle, Output, OutputStream};
+#[cfg(feature = "serde")]
+use crate::serialize::{display, exit_status, io_error, utf8_error};
+#[cfg(unix)]
+use crate::Resource;
+use crate::{
+    audit::Audit, redact::Redactor, report::Context, trace::Trace, ExitStatus, Handle, Output,
+    OutputStream, PropagateExit as _, Version, VersionReq,
+};
 use std::{
     error::Error as StdError,
     fmt::{self, Display},
-    io, process, str,
+    io,
+    path::PathBuf,
+    process, str,
+    sync::Arc,
+    time::Duration,
 };
 
-/// The specific cause of an [`Error`].
+/// The specific cause of an [`Error`]. More of these get added as bossy learns
+/// to tell failures apart, so matching on this needs a catch-all arm.
 #[derive(Debug)]
+#[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum Cause {
-    SpawnFailed(io::Error),
-    WaitFailed(io::Error),
-    CommandFailed(ExitStatus),
+    SpawnFailed(#[cfg_attr(feature = "serde", serde(with = "io_error"))] io::Error),
+    WaitFailed(#[cfg_attr(feature = "serde", serde(with = "io_error"))] io::Error),
+    CommandFailed(#[cfg_attr(feature = "serde", serde(with = "exit_status"))] ExitStatus),
     CommandFailedWithOutput(Output),
     InvalidUtf8 {
         stream: OutputStream,
+        #[cfg_attr(feature = "serde", serde(with = "utf8_error"))]
         source: std::str::Utf8Error,
     },
+    VersionNotFound {
+        pattern: String,
+    },
+    VersionMismatch {
+        program: String,
+        #[cfg_attr(feature = "serde", serde(with = "display"))]
+        found: Version,
+        #[cfg_attr(feature = "serde", serde(with = "display"))]
+        required: VersionReq,
+    },
+    EnvFileReadFailed {
+        path: PathBuf,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    EnvFileInvalid {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    PtyFailed(#[cfg_attr(feature = "serde", serde(with = "io_error"))] io::Error),
+    /// The command was killed before it could finish, but here's whatever it
+    /// managed to output first.
+    Cancelled(Output),
+    /// The command was never run, since another command it was grouped with
+    /// failed first.
+    Skipped,
+    /// A [`Session`](crate::Session) didn't see what it was waiting for in
+    /// time. `buffer` is everything it saw instead (with secrets redacted).
+    ExpectTimedOut {
+        expected: String,
+        timeout: Duration,
+        buffer: String,
+    },
+    /// The command closed its output before a [`Session`](crate::Session) saw
+    /// what it was waiting for.
+    ExpectEof {
+        expected: String,
+        buffer: String,
+    },
+    SessionIoFailed(#[cfg_attr(feature = "serde", serde(with = "io_error"))] io::Error),
+    UserNotFound {
+        user: String,
+    },
+    UserLookupFailed {
+        user: String,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    SetGroupsFailed {
+        groups: Vec<u32>,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    SetGidFailed {
+        gid: u32,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    SetUidFailed {
+        uid: u32,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    /// The kernel wouldn't create namespaces for a
+    /// [`Sandbox`](crate::Sandbox), which usually means unprivileged user
+    /// namespaces are disabled.
+    SandboxUnavailable(#[cfg_attr(feature = "serde", serde(with = "io_error"))] io::Error),
+    SandboxSetupFailed {
+        step: String,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    SeccompFailed {
+        profiles: String,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    /// An extra file descriptor couldn't be passed to the command.
+    FdFailed {
+        fd: i32,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    /// A resource limit couldn't be set on the command, usually from trying
+    /// to raise a hard limit without the privileges to.
+    #[cfg(unix)]
+    RlimitFailed {
+        resource: Resource,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    PidfileFailed {
+        path: PathBuf,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    LogFileFailed {
+        path: PathBuf,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
+    /// Another daemon is holding the lock on the pidfile. `pid` is what it
+    /// wrote there, if anything.
+    DaemonAlreadyRunning {
+        pidfile: PathBuf,
+        pid: Option<u32>,
+    },
+    /// A signal couldn't be caught to forward it to the command, so the
+    /// command was never run.
+    SignalForwardingFailed {
+        signal: i32,
+        #[cfg_attr(feature = "serde", serde(with = "io_error"))]
+        source: io::Error,
+    },
 }
 
 impl Cause {
@@ -31,8 +162,12 @@ impl Cause {
         }
     }
 
-    fn from_output(command: String, output: process::Output) -> Result<Output, Self> {
-        let output = Output::new(command, output);
+    fn from_output(
+        command: String,
+        redactor: Arc<Redactor>,
+        output: process::Output,
+    ) -> Result<Output, Self> {
+        let output = Output::new(command, redactor, output);
         if output.success() {
             Ok(output)
         } else {
@@ -49,23 +184,34 @@ impl Cause {
     }
 
     fn output(&self) -> Option<&Output> {
-        if let Self::CommandFailedWithOutput(output) = self {
-            Some(output)
-        } else {
-            None
+        match self {
+            Self::CommandFailedWithOutput(output) | Self::Cancelled(output) => Some(output),
+            _ => None,
+        }
+    }
+
+    fn buffer(&self) -> Option<&str> {
+        match self {
+            Self::ExpectTimedOut { buffer, .. } | Self::ExpectEof { buffer, .. } => Some(buffer),
+            _ => None,
         }
     }
 }
 
 /// The bearer of bad news.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Error {
     command: String,
-    cause: Cause,
+    // Boxed so that errors don't make every `Result` huge.
+    cause: Box<Cause>,
+    note: Option<String>,
+    // What we knew about the command, for `Error::report`.
+    context: Option<Arc<Context>>,
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl Error {
+    pub(crate) fn fmt_cause(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         fn command_failed(
             f: &mut fmt::Formatter,
             command: &str,
@@ -73,13 +219,26 @@ impl Display for Error {
         ) -> fmt::Result {
             write!(f, "Command {:?} didn't complete successfully, ", command)?;
             if let Some(exit_code) = status.code() {
-                write!(f, "exiting with code {}.", exit_code)
-            } else {
-                write!(f, "but returned no exit code.")
+                return write!(f, "exiting with code {}.", exit_code);
+            }
+            #[cfg(unix)]
+            {
+                use std::os::unix::process::ExitStatusExt as _;
+                if let Some(signal) = status.signal() {
+                    write!(f, "getting killed by signal {}", signal)?;
+                    if let Some(resource) = Resource::from_signal(signal) {
+                        write!(f, " for exceeding its {} limit", resource.description())?;
+                    }
+                    if signal == libc::SIGSYS {
+                        write!(f, " for making a disallowed syscall")?;
+                    }
+                    return write!(f, ".");
+                }
             }
+            write!(f, "but returned no exit code.")
         }
 
-        match &self.cause {
+        match &*self.cause {
             Cause::SpawnFailed(err) => write!(
                 f,
                 "Failed to spawn child process for command {:?}: {}",
@@ -94,36 +253,236 @@ impl Display for Error {
             Cause::CommandFailedWithOutput(output) => {
                 command_failed(f, &self.command, output.status())?;
                 if !output.stderr().is_empty() {
-                    write!(
-                        f,
-                        " stderr contents: {}",
-                        String::from_utf8_lossy(output.stderr())
-                    )
+                    write!(f, " stderr contents: {}", output.stderr_for_display())
                 } else {
                     write!(f, " stderr was empty.")
                 }
             }
+            Cause::PtyFailed(err) => write!(
+                f,
+                "Failed to open pseudo-terminal for command {:?}: {}",
+                self.command, err
+            ),
+            Cause::Cancelled(output) => {
+                write!(f, "Command {:?} was cancelled.", self.command)?;
+                if !output.stderr().is_empty() {
+                    write!(
+                        f,
+                        " stderr contents before cancellation: {}",
+                        output.stderr_for_display()
+                    )?;
+                }
+                Ok(())
+            }
+            Cause::Skipped => write!(
+                f,
+                "Command {:?} was skipped, since another command failed first.",
+                self.command
+            ),
+            Cause::ExpectTimedOut {
+                expected,
+                timeout,
+                buffer,
+            } => write!(
+                f,
+                "Timed out after {:?} waiting for {} from command {:?}. Output so far: {:?}",
+                timeout, expected, self.command, buffer
+            ),
+            Cause::ExpectEof { expected, buffer } => write!(
+                f,
+                "Command {:?} closed its output before we saw {}. Output so far: {:?}",
+                self.command, expected, buffer
+            ),
+            Cause::SessionIoFailed(err) => write!(
+                f,
+                "Failed to talk to interactive command {:?}: {}",
+                self.command, err
+            ),
+            Cause::UserNotFound { user } => write!(
+                f,
+                "User {:?} for command {:?} doesn't exist",
+                user, self.command
+            ),
+            Cause::UserLookupFailed { user, source } => write!(
+                f,
+                "Failed to look up user {:?} for command {:?}: {}",
+                user, self.command, source
+            ),
+            Cause::SetGroupsFailed { groups, source } => write!(
+                f,
+                "Failed to set supplementary groups to {:?} for command {:?}: {}",
+                groups, self.command, source
+            ),
+            Cause::SetGidFailed { gid, source } => write!(
+                f,
+                "Failed to set group ID to {} for command {:?}: {}",
+                gid, self.command, source
+            ),
+            Cause::SetUidFailed { uid, source } => write!(
+                f,
+                "Failed to set user ID to {} for command {:?}: {}",
+                uid, self.command, source
+            ),
+            Cause::SandboxUnavailable(err) => write!(
+                f,
+                "Failed to create namespaces to sandbox command {:?}: {}. Unprivileged user namespaces might be disabled (see `/proc/sys/user/max_user_namespaces`, or `kernel.apparmor_restrict_unprivileged_userns` on Ubuntu).",
+                self.command, err
+            ),
+            Cause::SandboxSetupFailed { step, source } => write!(
+                f,
+                "Failed to set up sandbox for command {:?} while {}: {}",
+                self.command, step, source
+            ),
+            Cause::SeccompFailed { profiles, source } => write!(
+                f,
+                "Failed to install {} for command {:?}: {}",
+                profiles, self.command, source
+            ),
+            Cause::FdFailed { fd, source } => write!(
+                f,
+                "Failed to pass file descriptor {} to command {:?}: {}",
+                fd, self.command, source
+            ),
+            #[cfg(unix)]
+            Cause::RlimitFailed { resource, source } => write!(
+                f,
+                "Failed to set the {} limit for command {:?}: {}",
+                resource.description(),
+                self.command,
+                source
+            ),
+            Cause::PidfileFailed { path, source } => write!(
+                f,
+                "Failed to write pidfile {:?} for command {:?}: {}",
+                path, self.command, source
+            ),
+            Cause::LogFileFailed { path, source } => write!(
+                f,
+                "Failed to open log file {:?} for command {:?}: {}",
+                path, self.command, source
+            ),
+            Cause::DaemonAlreadyRunning { pidfile, pid } => {
+                write!(
+                    f,
+                    "Didn't start command {:?}, since pidfile {:?} is locked by another daemon",
+                    self.command, pidfile
+                )?;
+                if let Some(pid) = pid {
+                    write!(f, " with pid {}", pid)?;
+                }
+                write!(f, ".")
+            }
+            Cause::SignalForwardingFailed { signal, source } => write!(
+                f,
+                "Failed to catch signal {} to forward to command {:?}: {}",
+                signal, self.command, source
+            ),
             Cause::InvalidUtf8 { stream, source, .. } => write!(
                 f,
                 "{} for command {:?} contained invalid UTF-8: {}",
                 stream, self.command, source,
             ),
+            Cause::VersionNotFound { pattern } => write!(
+                f,
+                "Output of command {:?} didn't contain a version matching pattern {:?}",
+                self.command, pattern,
+            ),
+            Cause::VersionMismatch {
+                program,
+                found,
+                required,
+            } => write!(f, "{} {} found, {} required", program, found, required),
+            Cause::EnvFileReadFailed { path, source } => write!(
+                f,
+                "Failed to read env file {:?} for command {:?}: {}",
+                path, self.command, source
+            ),
+            Cause::EnvFileInvalid {
+                path,
+                line,
+                message,
+            } => write!(
+                f,
+                "Env file {:?} for command {:?} is invalid at line {}: {}",
+                path, self.command, line, message
+            ),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_cause(f)?;
+        if let Some(note) = &self.note {
+            write!(f, " {}", note)?;
         }
+        Ok(())
     }
 }
 
 impl StdError for Error {
     fn source(&self) -> Option<&(dyn StdError + 'static)> {
-        match &self.cause {
+        match &*self.cause {
             Cause::SpawnFailed(err) => Some(err as _),
             Cause::WaitFailed(err) => Some(err as _),
             Cause::InvalidUtf8 { source, .. } => Some(source as _),
+            Cause::EnvFileReadFailed { source, .. } => Some(source as _),
+            Cause::PtyFailed(err) => Some(err as _),
+            Cause::SessionIoFailed(err) => Some(err as _),
+            Cause::UserLookupFailed { source, .. }
+            | Cause::SetGroupsFailed { source, .. }
+            | Cause::SetGidFailed { source, .. }
+            | Cause::SetUidFailed { source, .. }
+            | Cause::SandboxSetupFailed { source, .. }
+            | Cause::SeccompFailed { source, .. }
+            | Cause::FdFailed { source, .. }
+            | Cause::PidfileFailed { source, .. }
+            | Cause::LogFileFailed { source, .. }
+            | Cause::SignalForwardingFailed { source, .. } => Some(source as _),
+            #[cfg(unix)]
+            Cause::RlimitFailed { source, .. } => Some(source as _),
+            Cause::SandboxUnavailable(err) => Some(err as _),
             _ => None,
         }
     }
 }
 
 impl Error {
+    pub(crate) fn new(command: String, cause: Cause) -> Self {
+        Self {
+            command,
+            cause: Box::new(cause),
+            note: None,
+            context: None,
+        }
+    }
+
+    pub(crate) fn with_context(mut self, context: Arc<Context>) -> Self {
+        if self.context.is_none() {
+            self.context = Some(context);
+        }
+        self
+    }
+
+    pub(crate) fn context(&self) -> Option<&Context> {
+        self.context.as_deref()
+    }
+
+    /// What went wrong, for when you need to handle some failures
+    /// differently.
+    pub fn cause(&self) -> &Cause {
+        &self.cause
+    }
+
+    /// Adds some extra context to the end of the error message.
+    pub(crate) fn with_note(mut self, note: String) -> Self {
+        self.note = Some(match self.note.take() {
+            Some(existing) => format!("{} {}", existing, note),
+            None => note,
+        });
+        self
+    }
+
     pub(crate) fn from_status_result(
         command: String,
         result: io::Result<process::ExitStatus>,
@@ -131,30 +490,66 @@ impl Error {
         result
             .map_err(Cause::from_io_err)
             .and_then(Cause::from_status)
-            .map_err(|cause| Self { command, cause })
+            .map_err(|cause| Self::new(command, cause))
     }
 
     pub(crate) fn from_output_result(
         command: String,
+        redactor: Arc<Redactor>,
         result: io::Result<process::Output>,
     ) -> Result<Output, Self> {
         result
             .map_err(Cause::from_io_err)
-            .and_then(|output| Cause::from_output(command.clone(), output))
-            .map_err(|cause| Self { command, cause })
+            .and_then(|output| Cause::from_output(command.clone(), redactor, output))
+            .map_err(|cause| Self::new(command, cause))
+    }
+
+    pub(crate) fn from_cancelled_output_result(
+        command: String,
+        redactor: Arc<Redactor>,
+        result: io::Result<process::Output>,
+    ) -> Self {
+        let cause = match result {
+            Ok(output) => Cause::Cancelled(Output::new(command.clone(), redactor, output)),
+            Err(err) => Cause::from_io_err(err),
+        };
+        Self::new(command, cause)
+    }
+
+    /// For a command that was cancelled before it was even spawned, which
+    /// looks the same as one that was killed right away.
+    pub(crate) fn cancelled_before_spawn(command: String, redactor: Arc<Redactor>) -> Self {
+        #[cfg(unix)]
+        let status = {
+            use std::os::unix::process::ExitStatusExt as _;
+            ExitStatus::from_raw(libc::SIGKILL)
+        };
+        #[cfg(windows)]
+        let status = {
+            use std::os::windows::process::ExitStatusExt as _;
+            // This is the code `Child::kill` exits with.
+            ExitStatus::from_raw(1)
+        };
+        let output = process::Output {
+            status,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        };
+        let cause = Cause::Cancelled(Output::new(command.clone(), redactor, output));
+        Self::new(command, cause)
     }
 
     pub(crate) fn from_child_result(
         command: String,
+        redactor: Arc<Redactor>,
+        trace: Trace,
+        audit: Audit,
         result: io::Result<process::Child>,
     ) -> Result<Handle, Self> {
         // `match` is favored here to avoid cloning `command`
         match result {
-            Ok(child) => Ok(Handle::new(command, child)),
-            Err(err) => Err(Self {
-                command,
-                cause: Cause::from_io_err(err),
-            }),
+            Ok(child) => Ok(Handle::new(command, redactor, trace, audit, child)),
+            Err(err) => Err(Self::new(command, Cause::SpawnFailed(err))),
         }
     }
 
@@ -163,16 +558,20 @@ impl Error {
         stream: OutputStream,
         result: Result<&'a str, std::str::Utf8Error>,
     ) -> Result<&'a str, Self> {
-        result.map_err(|source| Self {
-            command: command.to_owned(),
-            cause: Cause::InvalidUtf8 { stream, source },
-        })
+        result
+            .map_err(|source| Self::new(command.to_owned(), Cause::InvalidUtf8 { stream, source }))
     }
 
     pub fn command(&self) -> &str {
         &self.command
     }
 
+    /// Extra context about what might have gone wrong, which is also included
+    /// at the end of the error message.
+    pub fn note(&self) -> Option<&str> {
+        self.note.as_deref()
+    }
+
     pub fn status(&self) -> Option<ExitStatus> {
         self.cause.status()
     }
@@ -181,6 +580,47 @@ impl Error {
         self.status().and_then(|status| status.code())
     }
 
+    /// The signal that killed the command, if that's how it died.
+    #[cfg(unix)]
+    pub fn signal(&self) -> Option<i32> {
+        use std::os::unix::process::ExitStatusExt as _;
+        self.status().and_then(|status| status.signal())
+    }
+
+    /// The resource limit the command was killed for exceeding, if any. See
+    /// [`Command::set_rlimit`](crate::Command::set_rlimit).
+    #[cfg(unix)]
+    pub fn exceeded_rlimit(&self) -> Option<Resource> {
+        self.signal().and_then(Resource::from_signal)
+    }
+
+    /// The code to exit with to pass this error along. Commands that ran use
+    /// [`PropagateExit::exit_code`](crate::PropagateExit::exit_code), and
+    /// commands that couldn't be found or run get 127 and 126 respectively
+    /// (the same as shells use). Anything else is 1.
+    pub fn exit_code(&self) -> i32 {
+        if let Some(status) = self.status() {
+            return status.exit_code();
+        }
+        match &*self.cause {
+            Cause::SpawnFailed(err) if err.kind() == io::ErrorKind::NotFound => 127,
+            Cause::SpawnFailed(err) if err.kind() == io::ErrorKind::PermissionDenied => 126,
+            _ => 1,
+        }
+    }
+
+    /// Print a [`Report`](crate::Report) of this error to stderr and exit,
+    /// the same way the command did if it got to run. See
+    /// [`PropagateExit::exit`](crate::PropagateExit::exit).
+    pub fn exit(&self) -> ! {
+        log::debug!("exiting due to error: {}", self);
+        eprint!("{}", self.report());
+        match self.status() {
+            Some(status) => status.exit(),
+            None => process::exit(self.exit_code()),
+        }
+    }
+
     pub fn output(&self) -> Option<&Output> {
         self.cause.output()
     }
@@ -200,4 +640,9 @@ impl Error {
     pub fn stderr_str(&self) -> Option<crate::Result<&str>> {
         self.output().map(|output| output.stderr_str())
     }
+
+    /// Everything a [`Session`](crate::Session) saw before it gave up waiting.
+    pub fn session_buffer(&self) -> Option<&str> {
+        self.cause.buffer()
+    }
 }
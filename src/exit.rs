@@ -0,0 +1,84 @@
+use crate::ExitStatus;
+use std::{
+    io::{self, Write as _},
+    process,
+};
+
+/// For binaries that wrap a command, and want to exit the same way it did.
+pub trait PropagateExit {
+    /// The code to exit with: the command's own exit code, or 128 plus the
+    /// signal that killed it (the same as shells report it).
+    fn exit_code(&self) -> i32;
+
+    /// Exit the same way the command did. If it was killed by a signal, then
+    /// we kill ourselves with the same signal, unless that would dump core.
+    fn exit(&self) -> !;
+}
+
+impl PropagateExit for ExitStatus {
+    fn exit_code(&self) -> i32 {
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt as _;
+            if let Some(signal) = self.signal() {
+                return 128 + signal;
+            }
+        }
+        // This is only `None` if the command was killed by a signal.
+        self.code().unwrap_or(1)
+    }
+
+    fn exit(&self) -> ! {
+        // Killing ourselves would skip this.
+        let _ = io::stdout().flush();
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt as _;
+            if let Some(signal) = self.signal().filter(|_| !self.core_dumped()) {
+                log::info!("exiting by raising signal {}", signal);
+                unsafe {
+                    let mut set = std::mem::zeroed::<libc::sigset_t>();
+                    libc::sigemptyset(&mut set);
+                    libc::sigaddset(&mut set, signal);
+                    libc::pthread_sigmask(libc::SIG_UNBLOCK, &set, std::ptr::null_mut());
+                    libc::signal(signal, libc::SIG_DFL);
+                    libc::raise(signal);
+                }
+                // If we're still here, then the signal doesn't kill by
+                // default, so we fall back on the exit code.
+            }
+        }
+        process::exit(self.exit_code())
+    }
+}
+
+/// A result that `main` can return, which exits the same way the command did
+/// if it failed. See [`Error::exit`](crate::Error::exit).
+#[derive(Debug)]
+#[must_use = "this does nothing unless it's returned from `main`"]
+pub struct Exit<T = ()>(crate::Result<T>);
+
+impl<T> From<crate::Result<T>> for Exit<T> {
+    fn from(result: crate::Result<T>) -> Self {
+        Self(result)
+    }
+}
+
+impl<T> Exit<T> {
+    pub fn new(result: crate::Result<T>) -> Self {
+        Self(result)
+    }
+
+    pub fn into_result(self) -> crate::Result<T> {
+        self.0
+    }
+}
+
+impl<T> process::Termination for Exit<T> {
+    fn report(self) -> process::ExitCode {
+        match self.0 {
+            Ok(_) => process::ExitCode::SUCCESS,
+            Err(err) => err.exit(),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use crate::pre_exec::Step;
+use std::{
+    collections::BTreeMap,
+    ffi::{CString, OsString},
+    fs::File,
+    io,
+    os::unix::{
+        ffi::{OsStrExt as _, OsStringExt as _},
+        io::{AsRawFd as _, BorrowedFd, OwnedFd, RawFd},
+        net::UnixStream,
+    },
+};
+
+/// Our ends of the pipes passed to the child, by the child's fd.
+pub(crate) type Pipes = Vec<(RawFd, UnixStream)>;
+
+/// Where a file descriptor passed to the child comes from.
+#[derive(Debug)]
+enum Source {
+    /// Our own copy of a file descriptor we were given.
+    Fd(OwnedFd),
+    /// The child's end of a socket pair, which is only open while spawning.
+    Pipe(Option<OwnedFd>),
+}
+
+impl Source {
+    fn raw(&self) -> Option<RawFd> {
+        match self {
+            Self::Fd(fd) | Self::Pipe(Some(fd)) => Some(fd.as_raw_fd()),
+            Self::Pipe(None) => None,
+        }
+    }
+}
+
+/// Extra file descriptors to pass to the child, beyond stdio.
+#[derive(Debug, Default)]
+pub(crate) struct Fds {
+    mappings: Vec<(RawFd, Source)>,
+    // Somewhere to keep temporary copies in the child, since it can't
+    // allocate.
+    scratch: Vec<RawFd>,
+}
+
+impl Fds {
+    fn set(&mut self, child_fd: RawFd, source: Source) {
+        match self.mappings.iter_mut().find(|(fd, _)| *fd == child_fd) {
+            Some((_, existing)) => *existing = source,
+            None => self.mappings.push((child_fd, source)),
+        }
+        self.scratch.resize(self.mappings.len(), -1);
+    }
+
+    /// Passes a copy of `fd`, so the caller is free to close theirs.
+    pub fn insert(&mut self, child_fd: RawFd, fd: BorrowedFd<'_>) -> io::Result<()> {
+        // This makes sure the copy is close-on-exec, and isn't stdio.
+        let copy = fd.try_clone_to_owned()?;
+        self.set(child_fd, Source::Fd(copy));
+        Ok(())
+    }
+
+    pub fn insert_pipe(&mut self, child_fd: RawFd) {
+        self.set(child_fd, Source::Pipe(None));
+    }
+
+    /// Which child fd the step's index refers to.
+    pub fn child_fd(&self, index: u8) -> Option<RawFd> {
+        self.mappings.get(usize::from(index)).map(|(fd, _)| *fd)
+    }
+
+    /// Opens a fresh socket pair for each pipe, returning our ends. If that
+    /// fails, then the error comes with the child fd it was for.
+    pub fn open_pipes(&mut self) -> Result<Pipes, (RawFd, io::Error)> {
+        let mut ours = Vec::new();
+        for (child_fd, source) in &mut self.mappings {
+            if let Source::Pipe(theirs) = source {
+                let (our_end, their_end) = UnixStream::pair().map_err(|err| (*child_fd, err))?;
+                *theirs = Some(their_end.into());
+                ours.push((*child_fd, our_end));
+            }
+        }
+        Ok(ours)
+    }
+
+    /// Closes our copies of the child's ends of the pipes, since otherwise
+    /// we'd never see the child close them.
+    pub fn close_pipes(&mut self) {
+        for (_, source) in &mut self.mappings {
+            if let Source::Pipe(theirs) = source {
+                *theirs = None;
+            }
+        }
+    }
+
+    /// Makes sure every fd up to the highest one we're passing is open in the
+    /// parent while spawning. Otherwise, std could put the pipe it uses to
+    /// report `exec` failures there, which we'd then clobber in the child.
+    pub fn reserve(&self) -> Result<Vec<File>, (RawFd, io::Error)> {
+        let max = match self.mappings.iter().map(|(fd, _)| *fd).max() {
+            Some(max) => max,
+            None => return Ok(Vec::new()),
+        };
+        let mut reserved = Vec::new();
+        loop {
+            let file = File::open("/dev/null").map_err(|err| (max, err))?;
+            if file.as_raw_fd() > max {
+                break;
+            }
+            reserved.push(file);
+        }
+        Ok(reserved)
+    }
+
+    /// This is meant to run in the child between `fork` and `exec`, so it
+    /// can't allocate.
+    pub fn apply(&mut self) -> io::Result<()> {
+        let max = match self.mappings.iter().map(|(fd, _)| *fd).max() {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        // Everything gets copied out of the way first, since otherwise one
+        // mapping could clobber the source of another. These copies are
+        // closed by `exec`.
+        for (index, ((_, source), scratch)) in
+            self.mappings.iter().zip(&mut self.scratch).enumerate()
+        {
+            let step = Step::PassFd(index as u8);
+            let fd = source
+                .raw()
+                .ok_or_else(|| step.tag(io::Error::from_raw_os_error(libc::EBADF)))?;
+            *scratch = step.check(unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, max + 1) })?;
+        }
+        // `dup2` leaves close-on-exec unset on the new fd, which is exactly
+        // what we want.
+        for (index, ((child_fd, _), scratch)) in self.mappings.iter().zip(&self.scratch).enumerate()
+        {
+            Step::PassFd(index as u8).check(unsafe { libc::dup2(*scratch, *child_fd) })?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the environment `execvp` uses is kept. On Apple platforms, programs
+/// can't link to `environ` directly, so we have to ask for it.
+#[cfg(target_vendor = "apple")]
+unsafe fn environ() -> *mut *const *const libc::c_char {
+    extern "C" {
+        fn _NSGetEnviron() -> *mut *const *const libc::c_char;
+    }
+    _NSGetEnviron()
+}
+
+#[cfg(not(target_vendor = "apple"))]
+unsafe fn environ() -> *mut *const *const libc::c_char {
+    extern "C" {
+        static mut environ: *const *const libc::c_char;
+    }
+    std::ptr::addr_of_mut!(environ)
+}
+
+const LISTEN_PID: &[u8] = b"LISTEN_PID=";
+
+/// Execs the command ourselves, since the child's pid isn't known until after
+/// `fork`, and std gives us no way to add `LISTEN_PID` to its environment then.
+#[derive(Debug)]
+pub(crate) struct Exec {
+    program: CString,
+    // These own what `argv` and `envp` point to.
+    _args: Vec<CString>,
+    _env: Vec<CString>,
+    // `LISTEN_PID=` followed by room for the pid.
+    pid_var: Vec<u8>,
+    pid_ptr: *mut u8,
+    argv: Vec<*const libc::c_char>,
+    envp: Vec<*const libc::c_char>,
+}
+
+// The pointers only ever point into buffers we own.
+unsafe impl Send for Exec {}
+
+impl Exec {
+    pub fn new(
+        program: &std::ffi::OsStr,
+        args: impl IntoIterator<Item = OsString>,
+        env: BTreeMap<OsString, OsString>,
+    ) -> io::Result<Self> {
+        fn c_string(bytes: Vec<u8>) -> io::Result<CString> {
+            CString::new(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+        }
+
+        let program = c_string(program.as_bytes().to_vec())?;
+        let args = std::iter::once(Ok(program.clone()))
+            .chain(args.into_iter().map(|arg| c_string(arg.into_vec())))
+            .collect::<io::Result<Vec<_>>>()?;
+        let env = env
+            .into_iter()
+            .filter(|(key, _)| key.as_bytes() != &LISTEN_PID[..LISTEN_PID.len() - 1])
+            .map(|(key, val)| {
+                let mut var = key.into_vec();
+                var.push(b'=');
+                var.extend(val.into_vec());
+                c_string(var)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        // A `u32` never has more than 10 digits, plus the nul.
+        let mut pid_var = LISTEN_PID.to_vec();
+        pid_var.resize(LISTEN_PID.len() + 11, 0);
+        let pid_ptr = pid_var.as_mut_ptr();
+        let argv = args
+            .iter()
+            .map(|arg| arg.as_ptr())
+            .chain(std::iter::once(std::ptr::null()))
+            .collect();
+        let envp = env
+            .iter()
+            .map(|var| var.as_ptr())
+            .chain(std::iter::once(pid_ptr as *const libc::c_char))
+            .chain(std::iter::once(std::ptr::null()))
+            .collect();
+        Ok(Self {
+            program,
+            _args: args,
+            _env: env,
+            pid_var,
+            pid_ptr,
+            argv,
+            envp,
+        })
+    }
+
+    /// This is meant to run in the child between `fork` and `exec`, so it
+    /// can't allocate. This only returns if `exec` fails.
+    pub fn exec(&mut self) -> io::Result<()> {
+        let mut pid = unsafe { libc::getpid() } as u32;
+        let mut digits = [0; 10];
+        let mut len = 0;
+        loop {
+            digits[len] = b'0' + (pid % 10) as u8;
+            pid /= 10;
+            len += 1;
+            if pid == 0 {
+                break;
+            }
+        }
+        debug_assert!(self.pid_var.len() > LISTEN_PID.len() + len);
+        unsafe {
+            for (i, digit) in digits[..len].iter().rev().enumerate() {
+                *self.pid_ptr.add(LISTEN_PID.len() + i) = *digit;
+            }
+            *self.pid_ptr.add(LISTEN_PID.len() + len) = 0;
+            // This is how std does it too.
+            *environ() = self.envp.as_ptr();
+            libc::execvp(self.program.as_ptr(), self.argv.as_ptr());
+        }
+        Err(io::Error::last_os_error())
+    }
+}
@@ -1,10 +1,384 @@
-use crate::{ChildStderr, ChildStdin, ChildStdout, Error, ExitStatus, Output};
-use std::{io, process};
+#[cfg(target_os = "linux")]
+use crate::pty::{self, PtyMaster};
+use crate::{
+    audit::Audit, redact::Redactor, report::Context, trace::Trace, CancellationToken, ChildStderr,
+    ChildStdin, ChildStdout, Error, ExitStatus, Output,
+};
+#[cfg(unix)]
+use crate::{fd::Pipes, signal::Catcher};
+#[cfg(unix)]
+use std::os::unix::{io::RawFd, net::UnixStream};
+use std::{
+    io::{self, Read, Write},
+    process,
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Output read from a child in the background, a chunk at a time. Reading has
+/// to happen in the background, since otherwise the child would block once
+/// the pipe's (or terminal's) buffer fills up.
+#[derive(Debug)]
+pub(crate) struct Reader(mpsc::Receiver<io::Result<Vec<u8>>>);
+
+impl Reader {
+    /// Reads from every stream at once, interleaving the chunks in the order
+    /// they arrive. The reader is finished once every stream is closed.
+    pub fn new(streams: Vec<Box<dyn Read + Send>>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        for mut stream in streams {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                loop {
+                    let chunk = match stream.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => Ok(buf[..n].to_vec()),
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        // Linux reports that every slave of a pseudo-terminal
+                        // has closed using `EIO`.
+                        #[cfg(target_os = "linux")]
+                        Err(err) if err.raw_os_error() == Some(libc::EIO) => break,
+                        Err(err) => Err(err),
+                    };
+                    let failed = chunk.is_err();
+                    // If nobody's listening anymore, then there's no point in
+                    // reading any more.
+                    if tx.send(chunk).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+        Self(rx)
+    }
+
+    pub fn single(stream: impl Read + Send + 'static) -> Self {
+        Self::new(vec![Box::new(stream)])
+    }
+
+    /// A reader that yields `bytes` and then finishes.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let (tx, rx) = mpsc::channel();
+        if !bytes.is_empty() {
+            tx.send(Ok(bytes)).unwrap();
+        }
+        Self(rx)
+    }
+
+    /// Waits for the next chunk, returning `None` once the reader's finished.
+    pub fn next_timeout(&self, timeout: Duration) -> Option<io::Result<Vec<u8>>> {
+        match self.0.recv_timeout(timeout) {
+            Ok(chunk) => Some(chunk),
+            Err(mpsc::RecvTimeoutError::Timeout) => Some(Ok(Vec::new())),
+            Err(mpsc::RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+    /// The same as `read_to_end`, but gives up once `deadline` passes,
+    /// returning whatever it managed to read.
+    pub fn read_until(self, deadline: Instant) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        loop {
+            match self.next_timeout(deadline.saturating_duration_since(Instant::now())) {
+                // Chunks are never empty, so this means we timed out.
+                Some(Ok(chunk)) if chunk.is_empty() => break,
+                Some(chunk) => output.extend(chunk?),
+                None => break,
+            }
+        }
+        Ok(output)
+    }
+
+    pub fn read_to_end(self) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        for chunk in self.0 {
+            output.extend(chunk?);
+        }
+        Ok(output)
+    }
+}
+
+/// Reads everything, or whatever arrives before `deadline` if there is one.
+fn join(reader: Option<Reader>, deadline: Option<Instant>) -> io::Result<Vec<u8>> {
+    match (reader, deadline) {
+        (Some(reader), Some(deadline)) => reader.read_until(deadline),
+        (Some(reader), None) => reader.read_to_end(),
+        (None, _) => Ok(Vec::new()),
+    }
+}
+
+#[derive(Debug)]
+enum Child {
+    Real(process::Child),
+    // A child attached to a pseudo-terminal, which we read everything from
+    // in the background.
+    #[cfg(target_os = "linux")]
+    Pty {
+        child: process::Child,
+        master: PtyMaster,
+        // This is `None` once a `Session` takes it.
+        reader: Option<Reader>,
+        strip_ansi: bool,
+    },
+    // What a dry run pretends the command did.
+    Fake(process::Output),
+}
+
+impl Child {
+    fn real_mut(&mut self) -> Option<&mut process::Child> {
+        match self {
+            Self::Real(child) => Some(child),
+            #[cfg(target_os = "linux")]
+            Self::Pty { child, .. } => Some(child),
+            Self::Fake(_) => None,
+        }
+    }
+
+    fn wait(&mut self) -> io::Result<process::ExitStatus> {
+        match self {
+            Self::Real(child) => child.wait(),
+            #[cfg(target_os = "linux")]
+            Self::Pty { child, .. } => child.wait(),
+            Self::Fake(output) => Ok(output.status),
+        }
+    }
+
+    fn wait_with_output(self) -> io::Result<process::Output> {
+        match self {
+            Self::Real(child) => child.wait_with_output(),
+            #[cfg(target_os = "linux")]
+            Self::Pty {
+                mut child,
+                reader,
+                strip_ansi,
+                ..
+            } => {
+                let status = child.wait()?;
+                Ok(process::Output {
+                    status,
+                    stdout: pty::clean_output(join(reader, None)?, strip_ansi),
+                    stderr: Vec::new(),
+                })
+            }
+            Self::Fake(output) => Ok(output),
+        }
+    }
+
+    /// The same as `wait`, but keeps an eye on `watch` while the child's
+    /// running.
+    fn wait_watched(&mut self, watch: &mut Watch<'_>) -> io::Result<process::ExitStatus> {
+        match self.real_mut() {
+            Some(child) if !watch.is_idle() => poll(child, |child| watch.tick(child)),
+            _ => self.wait(),
+        }
+    }
+
+    /// The same as `wait_with_output`, but keeps an eye on `watch` while the
+    /// child's running.
+    fn wait_with_output_watched(self, watch: &mut Watch<'_>) -> io::Result<process::Output> {
+        // How long to keep reading once a stopped child has exited. Anything
+        // it left running could hold onto its output forever.
+        const STOPPED_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+        if watch.is_idle() {
+            return self.wait_with_output();
+        }
+        // The last item is whether or not to strip ANSI escapes, if the child
+        // is attached to a pseudo-terminal.
+        let (mut child, stdout, stderr, pty_strip_ansi) = match self {
+            Self::Real(mut child) => {
+                // Just like `wait_with_output`, we close stdin so the child
+                // doesn't wait on us forever.
+                drop(child.stdin.take());
+                let stdout = child.stdout.take().map(Reader::single);
+                let stderr = child.stderr.take().map(Reader::single);
+                (child, stdout, stderr, None::<bool>)
+            }
+            #[cfg(target_os = "linux")]
+            Self::Pty {
+                child,
+                reader,
+                strip_ansi,
+                ..
+            } => (child, reader, None, Some(strip_ansi)),
+            Self::Fake(output) => return Ok(output),
+        };
+        let status = poll(&mut child, |child| watch.tick(child))?;
+        let deadline = Some(Instant::now() + STOPPED_READ_TIMEOUT).filter(|_| watch.stopped());
+        let stdout = join(stdout, deadline)?;
+        Ok(process::Output {
+            status,
+            stdout: match pty_strip_ansi {
+                #[cfg(target_os = "linux")]
+                Some(strip_ansi) => pty::clean_output(stdout, strip_ansi),
+                _ => stdout,
+            },
+            stderr: join(stderr, deadline)?,
+        })
+    }
+}
+
+/// Stops the child once a token's cancelled.
+#[derive(Debug)]
+struct Stopper {
+    token: CancellationToken,
+    stopped_at: Option<Instant>,
+    killed: bool,
+}
+
+impl Stopper {
+    fn tick(&mut self, child: &mut process::Child) {
+        // These can only fail if the child already exited, which the next
+        // `try_wait` will tell us about.
+        match (self.stopped_at, self.token.grace_period()) {
+            (None, grace) if self.token.is_cancelled() => {
+                self.stopped_at = Some(Instant::now());
+                if grace.is_some() {
+                    let _ = terminate(child);
+                } else {
+                    self.killed = true;
+                    let _ = child.kill();
+                }
+            }
+            (Some(stopped_at), Some(grace)) if !self.killed && stopped_at.elapsed() >= grace => {
+                self.killed = true;
+                let _ = child.kill();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Everything to keep an eye on while waiting for the child.
+#[derive(Debug, Default)]
+pub(crate) struct Watch<'a> {
+    stoppers: Vec<Stopper>,
+    #[cfg(unix)]
+    catcher: Option<&'a mut Catcher>,
+    #[cfg(not(unix))]
+    _catcher: std::marker::PhantomData<&'a mut ()>,
+}
+
+impl<'a> Watch<'a> {
+    /// Stop the child once `token` is cancelled.
+    pub fn cancel_on(&mut self, token: &CancellationToken) -> &mut Self {
+        self.stoppers.push(Stopper {
+            token: token.clone(),
+            stopped_at: None,
+            killed: false,
+        });
+        self
+    }
+
+    /// Forward any signals `catcher` catches to the child.
+    #[cfg(unix)]
+    pub fn forward(&mut self, catcher: &'a mut Catcher) -> &mut Self {
+        self.catcher = Some(catcher);
+        self
+    }
+
+    fn is_idle(&self) -> bool {
+        #[cfg(unix)]
+        let idle = self.catcher.is_none();
+        #[cfg(not(unix))]
+        let idle = true;
+        idle && self.stoppers.is_empty()
+    }
+
+    fn tick(&mut self, child: &mut process::Child) {
+        #[cfg(unix)]
+        if let Some(catcher) = &mut self.catcher {
+            catcher.forward(child.id());
+        }
+        for stopper in &mut self.stoppers {
+            stopper.tick(child);
+        }
+    }
+
+    /// Whether or not the child was stopped.
+    fn stopped(&self) -> bool {
+        self.stoppers
+            .iter()
+            .any(|stopper| stopper.stopped_at.is_some())
+    }
+}
+
+/// Waits for the child to exit, calling `tick` with it every so often in the
+/// meantime.
+fn poll(
+    child: &mut process::Child,
+    mut tick: impl FnMut(&mut process::Child),
+) -> io::Result<process::ExitStatus> {
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        tick(child);
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Asks the child to exit, which on Unix means sending it `SIGTERM`.
+/// Elsewhere, there's no such thing, so it's just killed.
+fn terminate(child: &mut process::Child) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } == -1 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        child.kill()
+    }
+}
 
 #[derive(Debug)]
 struct Inner {
     command: String,
-    inner: process::Child,
+    redactor: Arc<Redactor>,
+    trace: Trace,
+    audit: Audit,
+    inner: Child,
+    // A description of the seccomp profiles the child is running under.
+    seccomp: Option<String>,
+    cancellation: Option<CancellationToken>,
+    context: Option<Arc<Context>>,
+    #[cfg(unix)]
+    pipes: Pipes,
+}
+
+impl Inner {
+    fn contextualize<T>(
+        context: Option<Arc<Context>>,
+        result: crate::Result<T>,
+    ) -> crate::Result<T> {
+        match context {
+            Some(context) => result.map_err(|err| err.with_context(context)),
+            None => result,
+        }
+    }
+
+    /// Adds whatever we know about why the command might have failed.
+    fn explain<T>(seccomp: Option<String>, result: crate::Result<T>) -> crate::Result<T> {
+        result.map_err(|err| match (seccomp, err.status()) {
+            #[cfg(unix)]
+            (Some(profiles), Some(_)) if err.signal() == Some(libc::SIGSYS) => {
+                err.with_note(format!("The command was running under {}.", profiles))
+            }
+            (Some(profiles), Some(_)) => err.with_note(format!(
+                "The command was running under {}, so it might have failed from being denied a syscall.",
+                profiles
+            )),
+            _ => err,
+        })
+    }
 }
 
 /// A handle to a child process. You **must** call either [`Handle::wait`] or
@@ -23,17 +397,92 @@ impl Drop for Handle {
                 "handle for command {:?} dropped without being waited on",
                 inner.command
             );
+            inner.audit.dropped();
         }
     }
 }
 
 impl Handle {
-    pub(crate) fn new(command: String, inner: process::Child) -> Self {
+    pub(crate) fn new(
+        command: String,
+        redactor: Arc<Redactor>,
+        trace: Trace,
+        mut audit: Audit,
+        inner: process::Child,
+    ) -> Self {
+        trace.spawned(inner.id());
+        audit.spawned(inner.id());
+        Self {
+            inner: Some(Inner {
+                command,
+                redactor,
+                trace,
+                audit,
+                inner: Child::Real(inner),
+                seccomp: None,
+                cancellation: None,
+                context: None,
+                #[cfg(unix)]
+                pipes: Vec::new(),
+            }),
+        }
+    }
+
+    pub(crate) fn fake(
+        command: String,
+        redactor: Arc<Redactor>,
+        trace: Trace,
+        output: process::Output,
+    ) -> Self {
         Self {
-            inner: Some(Inner { command, inner }),
+            inner: Some(Inner {
+                command,
+                redactor,
+                trace,
+                audit: Audit::none(),
+                inner: Child::Fake(output),
+                seccomp: None,
+                cancellation: None,
+                context: None,
+                #[cfg(unix)]
+                pipes: Vec::new(),
+            }),
         }
     }
 
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_pty(mut self, master: PtyMaster, reader: Reader, strip_ansi: bool) -> Self {
+        let mut inner = Self::expect(self.inner.take());
+        inner.inner = match inner.inner {
+            Child::Real(child) => Child::Pty {
+                child,
+                master,
+                reader: Some(reader),
+                strip_ansi,
+            },
+            other => other,
+        };
+        self.inner = Some(inner);
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub(crate) fn with_seccomp(mut self, profiles: String) -> Self {
+        self.as_mut().seccomp = Some(profiles);
+        self
+    }
+
+    pub(crate) fn with_context(mut self, context: Arc<Context>) -> Self {
+        self.as_mut().context = Some(context);
+        self
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn with_pipes(mut self, pipes: Pipes) -> Self {
+        self.as_mut().pipes = pipes;
+        self
+    }
+
     fn expect<T>(opt: Option<T>) -> T {
         opt.expect("developer error: `Handle` vacant")
     }
@@ -46,33 +495,230 @@ impl Handle {
         Self::expect(self.inner.take())
     }
 
+    /// This is always `None` for dry runs.
     pub fn stdin(&mut self) -> Option<&mut ChildStdin> {
-        self.as_mut().inner.stdin.as_mut()
+        self.as_mut().inner.real_mut()?.stdin.as_mut()
     }
 
+    /// This is always `None` for dry runs.
     pub fn stdout(&mut self) -> Option<&mut ChildStdout> {
-        self.as_mut().inner.stdout.as_mut()
+        self.as_mut().inner.real_mut()?.stdout.as_mut()
     }
 
+    /// This is always `None` for dry runs.
     pub fn stderr(&mut self) -> Option<&mut ChildStderr> {
-        self.as_mut().inner.stderr.as_mut()
+        self.as_mut().inner.real_mut()?.stderr.as_mut()
+    }
+
+    /// The parent's end of the child's pseudo-terminal, if it was run with
+    /// [`Command::set_pty`](crate::Command::set_pty). This is how you write
+    /// to its stdin or resize its window.
+    #[cfg(target_os = "linux")]
+    pub fn pty(&mut self) -> Option<&mut PtyMaster> {
+        if let Child::Pty { master, .. } = &mut self.as_mut().inner {
+            Some(master)
+        } else {
+            None
+        }
+    }
+
+    /// Our end of the pipe passed to the child as `child_fd` with
+    /// [`Command::set_pipe_fd`](crate::Command::set_pipe_fd). This is always
+    /// `None` for dry runs.
+    #[cfg(unix)]
+    pub fn pipe_fd(&mut self, child_fd: RawFd) -> Option<&mut UnixStream> {
+        self.as_mut()
+            .pipes
+            .iter_mut()
+            .find(|(fd, _)| *fd == child_fd)
+            .map(|(_, pipe)| pipe)
+    }
+
+    /// The same as [`Handle::pipe_fd`], but takes the pipe so it can be moved
+    /// to another thread.
+    #[cfg(unix)]
+    pub fn take_pipe_fd(&mut self, child_fd: RawFd) -> Option<UnixStream> {
+        let pipes = &mut self.as_mut().pipes;
+        let index = pipes.iter().position(|(fd, _)| *fd == child_fd)?;
+        Some(pipes.remove(index).1)
+    }
+
+    pub(crate) fn command(&self) -> &str {
+        &Self::expect(self.inner.as_ref()).command
+    }
+
+    pub(crate) fn redactor(&self) -> &Arc<Redactor> {
+        &Self::expect(self.inner.as_ref()).redactor
+    }
+
+    /// Takes over reading everything the child outputs (stdout and stderr
+    /// merged), as long as it's piped or attached to a pseudo-terminal. Dry
+    /// runs give back their pretend stdout.
+    pub(crate) fn take_reader(&mut self) -> Option<Reader> {
+        match &mut self.as_mut().inner {
+            Child::Real(child) => {
+                let mut streams = Vec::<Box<dyn Read + Send>>::new();
+                if let Some(stdout) = child.stdout.take() {
+                    streams.push(Box::new(stdout));
+                }
+                if let Some(stderr) = child.stderr.take() {
+                    streams.push(Box::new(stderr));
+                }
+                if streams.is_empty() {
+                    None
+                } else {
+                    Some(Reader::new(streams))
+                }
+            }
+            #[cfg(target_os = "linux")]
+            Child::Pty { reader, .. } => reader.take(),
+            Child::Fake(output) => Some(Reader::from_bytes(output.stdout.clone())),
+        }
+    }
+
+    /// Whatever's connected to the child's stdin, be it a pipe or a
+    /// pseudo-terminal.
+    pub(crate) fn input(&mut self) -> Option<&mut dyn Write> {
+        match &mut self.as_mut().inner {
+            Child::Real(child) => child.stdin.as_mut().map(|stdin| stdin as &mut dyn Write),
+            #[cfg(target_os = "linux")]
+            Child::Pty { master, .. } => Some(master),
+            Child::Fake(_) => None,
+        }
     }
 
     pub fn kill(&mut self) -> io::Result<()> {
-        self.as_mut().inner.kill()
+        self.as_mut()
+            .inner
+            .real_mut()
+            .map(process::Child::kill)
+            .unwrap_or(Ok(()))
+    }
+
+    /// The child's process ID. This is always `None` for dry runs.
+    pub fn pid(&mut self) -> Option<u32> {
+        self.as_mut().inner.real_mut().map(|child| child.id())
+    }
+
+    /// Stop the child once `token` is cancelled, if it's still running by the
+    /// time we wait on it. Any token set on the command is already attached.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        self.as_mut().cancellation = Some(token);
+        self
+    }
+
+    pub fn with_cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.set_cancellation_token(token);
+        self
     }
 
     pub fn wait(self) -> crate::Result<ExitStatus> {
-        let Inner { command, mut inner } = self.take();
-        Error::from_status_result(command, inner.wait())
+        self.wait_watched(Watch::default())
+    }
+
+    /// The same as [`Handle::wait`], but keeps an eye on `watch` too.
+    pub(crate) fn wait_watched(self, mut watch: Watch<'_>) -> crate::Result<ExitStatus> {
+        let Inner {
+            command,
+            redactor,
+            trace,
+            audit,
+            mut inner,
+            seccomp,
+            cancellation,
+            context,
+            // These stay open until the child exits, so it doesn't get
+            // `SIGPIPE` for writing to them.
+            #[cfg(unix)]
+                pipes: _pipes,
+        } = self.take();
+        if let Some(token) = &cancellation {
+            watch.cancel_on(token);
+        }
+        let result = trace.in_scope(|| inner.wait_watched(&mut watch));
+        let result = if watch.stopped() {
+            // There's no output to speak of, but a cancelled error needs some.
+            Err(Error::from_cancelled_output_result(
+                command,
+                redactor,
+                result.map(|status| process::Output {
+                    status,
+                    stdout: Vec::new(),
+                    stderr: Vec::new(),
+                }),
+            ))
+        } else {
+            Inner::explain(seccomp, Error::from_status_result(command, result))
+        };
+        let result = Inner::contextualize(context, result);
+        trace.exited(match &result {
+            Ok(status) => Some(*status),
+            Err(err) => err.status(),
+        });
+        audit.waited(&result);
+        result
     }
 
     pub fn wait_for_output(self) -> crate::Result<Output> {
-        let Inner { command, inner } = self.take();
-        Error::from_output_result(command, inner.wait_with_output())
+        self.wait_for_output_watched(Watch::default())
+    }
+
+    /// The same as [`Handle::wait_for_output`], but keeps an eye on `watch`
+    /// too.
+    pub(crate) fn wait_for_output_watched(self, mut watch: Watch<'_>) -> crate::Result<Output> {
+        let Inner {
+            command,
+            redactor,
+            trace,
+            audit,
+            inner,
+            seccomp,
+            cancellation,
+            context,
+            #[cfg(unix)]
+                pipes: _pipes,
+        } = self.take();
+        if let Some(token) = &cancellation {
+            watch.cancel_on(token);
+        }
+        let result = trace.in_scope(|| inner.wait_with_output_watched(&mut watch));
+        let result = if watch.stopped() {
+            Err(Error::from_cancelled_output_result(
+                command, redactor, result,
+            ))
+        } else {
+            Inner::explain(
+                seccomp,
+                Error::from_output_result(command, redactor, result),
+            )
+        };
+        let result = Inner::contextualize(context, result);
+        Self::output_waited(&trace, &audit, &result);
+        result
+    }
+
+    fn output_waited(trace: &Trace, audit: &Audit, result: &crate::Result<Output>) {
+        if let Some(output) = result
+            .as_ref()
+            .ok()
+            .or_else(|| result.as_ref().err()?.output())
+        {
+            trace.stderr(output);
+            trace.exited(Some(output.status()));
+        } else {
+            trace.exited(None);
+        }
+        audit.waited_for_output(result);
     }
 
     pub fn leak(self) {
-        self.take();
+        self.take().audit.detached();
+    }
+
+    /// Takes over the audit record, for when this isn't the process it should
+    /// describe.
+    #[cfg(unix)]
+    pub(crate) fn take_audit(&mut self) -> Audit {
+        std::mem::replace(&mut self.as_mut().audit, Audit::none())
     }
 }
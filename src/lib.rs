@@ -1,31 +1,159 @@
 //! Opinionated convenience wrapper for `std::process::Command` and friends.
 //!
 //! Note that this re-exports [`std::process::ChildStdin`],
-//! [`std::process::ExitStatus`], and [`std::process::Stdio`], so the docs for
-//! those items below might seem a bit out of place.
+//! [`std::process::ExitStatus`], [`std::process::Stdio`], and
+//! [`regex::Regex`], so the docs for those items below might seem a bit out of
+//! place.
+//!
+//! With the `tracing` feature enabled, every run of a command gets its own
+//! span, with fields for the command, pid, cwd, exit code, and duration.
+//! Captured stderr is recorded as events on that span.
+//!
+//! With the `miette` feature enabled, [`Error`] implements
+//! `miette::Diagnostic`, with the same hints as [`Error::report`].
+//!
+//! With the `serde` feature enabled, [`Error`], [`Cause`], [`Output`], and
+//! [`OutputStream`] can be serialized and deserialized, so failures can be
+//! sent to another process and reported there. Secrets are redacted before
+//! they're serialized.
 
+mod ansi;
+mod audit;
+mod cancel;
+mod command_set;
+#[cfg(unix)]
+mod credentials;
+#[cfg(unix)]
+mod daemon;
+mod detach;
+mod dry_run;
+mod env_file;
 mod error;
+mod exit;
+#[cfg(unix)]
+mod fd;
 mod handle;
 mod output;
+#[cfg(unix)]
+mod pre_exec;
+#[cfg(target_os = "linux")]
+mod pty;
+mod redact;
+mod report;
+#[cfg(unix)]
+mod rlimit;
+#[cfg(target_os = "linux")]
+mod sandbox;
+#[cfg(target_os = "linux")]
+mod seccomp;
+#[cfg(feature = "serde")]
+mod serialize;
+mod session;
+#[cfg(unix)]
+mod signal;
+mod supervisor;
+mod task_graph;
+mod trace;
+mod version;
 
 mod result {
     pub type Result<T> = std::result::Result<T, crate::error::Error>;
 }
 
-pub use self::{error::*, handle::*, output::*, result::*};
+pub use self::{
+    ansi::ColorMode,
+    audit::{set_audit_log, AuditLog, AuditOutput},
+    cancel::CancellationToken,
+    command_set::{run_all, CommandSet, FailureMode, Failures},
+    detach::DetachOptions,
+    dry_run::{is_dry_run, scoped_dry_run, set_dry_run, DryRunScope},
+    error::*,
+    exit::{Exit, PropagateExit},
+    handle::*,
+    output::*,
+    redact::REDACTED,
+    report::Report,
+    result::*,
+    session::{Match, Session},
+    supervisor::{Restart, RestartPolicy, ServiceState, ServiceStatus, Supervisor},
+    task_graph::{CycleError, TaskGraph, TaskId, TaskReport, TaskResult},
+    version::*,
+};
+#[cfg(unix)]
+pub use self::{
+    daemon::{Daemon, DaemonOptions},
+    rlimit::Resource,
+};
+#[cfg(target_os = "linux")]
+pub use self::{
+    pty::{PtyMaster, PtySize},
+    sandbox::Sandbox,
+    seccomp::SeccompProfile,
+};
+pub use regex::Regex;
 pub use std::process::{ChildStderr, ChildStdin, ChildStdout, ExitStatus, Stdio};
 
+use self::{audit::Audit, handle::Watch, redact::Redactor, trace::Trace};
 use std::{
-    ffi::OsStr,
+    borrow::Cow,
+    collections::BTreeMap,
+    env,
+    ffi::{OsStr, OsString},
     fmt::{self, Display},
+    fs,
+    path::Path,
     process,
+    sync::Arc,
+};
+#[cfg(unix)]
+use std::{
+    io,
+    sync::{Mutex, MutexGuard},
 };
 
 /// Build and run commands to your heart's content.
-#[derive(Debug)]
 pub struct Command {
     inner: process::Command,
+    components: Vec<String>,
     display: String,
+    pure: bool,
+    redactor: Arc<Redactor>,
+    always_run: bool,
+    dry_run_output: Option<process::Output>,
+    // Whether stdin, stdout, and stderr were set, in that order.
+    configured_stdio: [bool; 3],
+    #[cfg(target_os = "linux")]
+    pty: Option<PtySize>,
+    #[cfg(target_os = "linux")]
+    pty_strip_ansi: bool,
+    // This is shared with a `pre_exec` hook, which is registered the first
+    // time anything needs doing in the child.
+    #[cfg(unix)]
+    hooks: Option<Arc<Mutex<pre_exec::Hooks>>>,
+    #[cfg(target_os = "linux")]
+    sandbox: Option<Sandbox>,
+    #[cfg(target_os = "linux")]
+    seccomp: Vec<SeccompProfile>,
+    #[cfg(unix)]
+    socket_activation: bool,
+    #[cfg(unix)]
+    forwarded_signals: Vec<i32>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Command");
+        // The inner command's `Debug` impl shows every arg and env var, so we
+        // can only show it if there's nothing to hide.
+        if self.redactor.is_empty() {
+            debug.field("inner", &self.inner);
+        }
+        debug
+            .field("display", &self.display)
+            .field("pure", &self.pure)
+            .finish()
+    }
 }
 
 impl Display for Command {
@@ -35,11 +163,40 @@ impl Display for Command {
 }
 
 impl Command {
+    fn clear_env(&mut self) {
+        self.pure = true;
+        self.inner.env_clear();
+    }
+
     fn push_display(&mut self, component: &OsStr) {
-        if !self.display.is_empty() {
-            self.display.push(' ');
-        }
-        self.display.push_str(component.to_string_lossy().as_ref());
+        self.components
+            .push(component.to_string_lossy().into_owned());
+        self.refresh_display();
+    }
+
+    fn refresh_display(&mut self) {
+        self.display = self
+            .redactor
+            .redact(&self.components.join(" "))
+            .into_owned();
+    }
+
+    fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.redactor.redact(text)
+    }
+
+    fn redact_env_var(&self, key: &OsStr, val: &OsStr) -> String {
+        self.redact(&format!(
+            "{}={}",
+            key.to_string_lossy(),
+            val.to_string_lossy()
+        ))
+        .into_owned()
+    }
+
+    fn add_secret(&mut self, secret: &OsStr) {
+        Arc::make_mut(&mut self.redactor).add_secret(secret.to_string_lossy().into_owned());
+        self.refresh_display();
     }
 
     /// Start building a command that inherits all env vars from the environment.
@@ -47,7 +204,28 @@ impl Command {
         let name = name.as_ref();
         let mut this = Self {
             inner: process::Command::new(name),
+            components: Default::default(),
             display: Default::default(),
+            pure: false,
+            redactor: Default::default(),
+            always_run: false,
+            dry_run_output: None,
+            configured_stdio: [false; 3],
+            #[cfg(target_os = "linux")]
+            pty: None,
+            #[cfg(target_os = "linux")]
+            pty_strip_ansi: false,
+            #[cfg(unix)]
+            hooks: None,
+            #[cfg(target_os = "linux")]
+            sandbox: None,
+            #[cfg(target_os = "linux")]
+            seccomp: Vec::new(),
+            #[cfg(unix)]
+            socket_activation: false,
+            #[cfg(unix)]
+            forwarded_signals: Vec::new(),
+            cancellation: None,
         };
         this.push_display(name);
         this
@@ -58,7 +236,7 @@ impl Command {
     /// for things to function as expected.
     pub fn pure(name: impl AsRef<OsStr>) -> Self {
         let mut this = Self::impure(name);
-        this.inner.env_clear();
+        this.clear_env();
         this
     }
 
@@ -86,7 +264,7 @@ impl Command {
     pub fn try_pure_parse(arg_str: impl AsRef<str>) -> Option<Self> {
         let mut this = Self::try_impure_parse(arg_str);
         if let Some(this) = this.as_mut() {
-            this.inner.env_clear();
+            this.clear_env();
         }
         this
     }
@@ -101,10 +279,693 @@ impl Command {
         &self.display
     }
 
+    /// Get the command's string representation, quoted so that it can be
+    /// pasted into a shell.
+    pub fn display_escaped(&self) -> String {
+        let escaped = self
+            .components
+            .iter()
+            .map(|component| dry_run::shell_escape(component))
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.redact(&escaped).into_owned()
+    }
+
+    /// Run this command even in dry-run mode, which is what you want for
+    /// commands that only read things.
+    pub fn set_always_run(&mut self) -> &mut Self {
+        log::debug!("setting command {:?} to always run", self.display);
+        self.always_run = true;
+        self
+    }
+
+    pub fn with_always_run(mut self) -> Self {
+        self.set_always_run();
+        self
+    }
+
+    /// Set what this command pretends to output in dry-run mode. By default,
+    /// it pretends to succeed without outputting anything.
+    pub fn set_dry_run_output(
+        &mut self,
+        code: i32,
+        stdout: impl Into<Vec<u8>>,
+        stderr: impl Into<Vec<u8>>,
+    ) -> &mut Self {
+        let stdout = stdout.into();
+        let stderr = stderr.into();
+        log::debug!(
+            "setting dry-run output to code {} with stdout {:?} and stderr {:?} on command {:?}",
+            code,
+            self.redact(&String::from_utf8_lossy(&stdout)),
+            self.redact(&String::from_utf8_lossy(&stderr)),
+            self.display
+        );
+        self.dry_run_output = Some(process::Output {
+            status: dry_run::exit_status(code),
+            stdout,
+            stderr,
+        });
+        self
+    }
+
+    pub fn with_dry_run_output(
+        mut self,
+        code: i32,
+        stdout: impl Into<Vec<u8>>,
+        stderr: impl Into<Vec<u8>>,
+    ) -> Self {
+        self.set_dry_run_output(code, stdout, stderr);
+        self
+    }
+
+    /// Run the command attached to a pseudo-terminal, so that it behaves like
+    /// it would when run interactively (colors, progress bars, etc.). Output
+    /// is still captured when using [`Command::run_and_wait_for_output`],
+    /// but since the terminal combines stdout and stderr, it'll all be in
+    /// stdout. Use [`Handle::pty`] to write to stdin or resize the window.
+    #[cfg(target_os = "linux")]
+    pub fn set_pty(&mut self, size: PtySize) -> &mut Self {
+        log::debug!(
+            "setting pty with size {:?} on command {:?}",
+            size,
+            self.display
+        );
+        if self.pty.replace(size).is_none() {
+            self.hooks().controlling_terminal = true;
+        }
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn with_pty(mut self, size: PtySize) -> Self {
+        self.set_pty(size);
+        self
+    }
+
+    /// Limit how much of `resource` the command can use, which is applied in
+    /// the child right before it starts. The soft limit is what's enforced,
+    /// and the hard limit is how high the command is allowed to raise the soft
+    /// limit itself. Use `u64::MAX` for no limit.
+    ///
+    /// If the command gets killed for going over its limit, then
+    /// [`Error::exceeded_rlimit`] will tell you which one, though that's
+    /// only detectable for [`Resource::CpuTime`] and [`Resource::FileSize`].
+    /// If the limit can't be set at all, spawning fails with
+    /// [`Cause::RlimitFailed`].
+    #[cfg(unix)]
+    pub fn set_rlimit(&mut self, resource: Resource, soft: u64, hard: u64) -> &mut Self {
+        log::debug!(
+            "setting rlimit for {:?} to {}/{} on command {:?}",
+            resource,
+            soft,
+            hard,
+            self.display
+        );
+        let limit = rlimit::Limit {
+            resource,
+            soft,
+            hard,
+        };
+        self.hooks().rlimits.push(limit);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn with_rlimit(mut self, resource: Resource, soft: u64, hard: u64) -> Self {
+        self.set_rlimit(resource, soft, hard);
+        self
+    }
+
+    /// Everything that has to be done in the child before `exec`. The hook
+    /// that does it is registered the first time this is used, since having
+    /// any hooks at all means std can't use `posix_spawn`.
+    #[cfg(unix)]
+    fn hooks(&mut self) -> MutexGuard<'_, pre_exec::Hooks> {
+        if self.hooks.is_none() {
+            let hooks = Arc::new(Mutex::new(pre_exec::Hooks::default()));
+            let hook = Arc::clone(&hooks);
+            unsafe {
+                use std::os::unix::process::CommandExt as _;
+                // Changing these takes `&mut self`, so nothing else can be
+                // holding the lock when we fork.
+                self.inner.pre_exec(move || {
+                    hook.lock()
+                        .unwrap_or_else(std::sync::PoisonError::into_inner)
+                        .apply()
+                });
+            }
+            self.hooks = Some(hooks);
+        }
+        self.hooks
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    /// Run the command as a different user. If the parent is root and no
+    /// groups are set with [`Command::set_groups`], then the command gets no
+    /// supplementary groups. If our group is root's, then a group has to be
+    /// set too (with [`Command::set_gid`] or [`Command::set_user`]), or
+    /// spawning fails, since the command would otherwise stay in root's group.
+    #[cfg(unix)]
+    pub fn set_uid(&mut self, uid: u32) -> &mut Self {
+        log::debug!("setting uid to {} on command {:?}", uid, self.display);
+        self.hooks().credentials().uid = Some(uid);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn with_uid(mut self, uid: u32) -> Self {
+        self.set_uid(uid);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn set_gid(&mut self, gid: u32) -> &mut Self {
+        log::debug!("setting gid to {} on command {:?}", gid, self.display);
+        self.hooks().credentials().gid = Some(gid);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn with_gid(mut self, gid: u32) -> Self {
+        self.set_gid(gid);
+        self
+    }
+
+    /// Set the command's supplementary groups.
+    #[cfg(unix)]
+    pub fn set_groups(&mut self, groups: impl IntoIterator<Item = u32>) -> &mut Self {
+        let groups = groups.into_iter().collect::<Vec<_>>();
+        log::debug!(
+            "setting groups to {:?} on command {:?}",
+            groups,
+            self.display
+        );
+        self.hooks().credentials().groups = Some(groups);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn with_groups(mut self, groups: impl IntoIterator<Item = u32>) -> Self {
+        self.set_groups(groups);
+        self
+    }
+
+    /// Run the command as the user named `name`, with their primary group and
+    /// supplementary groups. `HOME`, `USER`, and `LOGNAME` are also set to
+    /// match, since otherwise the command would use ours. If the user can't be
+    /// found, then the command is left unchanged.
+    #[cfg(unix)]
+    pub fn set_user(&mut self, name: &str) -> Result<&mut Self> {
+        log::debug!(
+            "setting user to {:?} on command {:?}",
+            self.redact(name),
+            self.display
+        );
+        let user = credentials::lookup_user(name)
+            .map_err(|cause| Error::new(self.display.clone(), cause))?;
+        {
+            let mut hooks = self.hooks();
+            let credentials = hooks.credentials();
+            credentials.uid = Some(user.uid);
+            credentials.gid = Some(user.gid);
+            credentials.groups = Some(user.groups);
+        }
+        Ok(self
+            .add_env_var("HOME", user.home)
+            .add_env_var("USER", name)
+            .add_env_var("LOGNAME", name))
+    }
+
+    #[cfg(unix)]
+    pub fn with_user(mut self, name: &str) -> Result<Self> {
+        self.set_user(name)?;
+        Ok(self)
+    }
+
+    /// Set the command's umask, which masks out permission bits on any files
+    /// it creates. This is given in the usual octal form, like `0o022`.
+    #[cfg(unix)]
+    pub fn set_umask(&mut self, umask: u32) -> &mut Self {
+        log::debug!("setting umask to {:o} on command {:?}", umask, self.display);
+        self.hooks().credentials().umask = Some(umask);
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn with_umask(mut self, umask: u32) -> Self {
+        self.set_umask(umask);
+        self
+    }
+
+    #[cfg(unix)]
+    fn check_credentials(&self) -> Result<()> {
+        let hooks = match self.hooks.as_ref() {
+            Some(hooks) => hooks
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+            None => return Ok(()),
+        };
+        match hooks.credentials.as_ref() {
+            Some(credentials) => credentials
+                .check()
+                .map_err(|cause| Error::new(self.display.clone(), cause)),
+            None => Ok(()),
+        }
+    }
+
+    /// Strip ANSI escapes from output captured from a pseudo-terminal. This
+    /// can be set before or after [`Command::set_pty`], but does nothing
+    /// without it.
+    #[cfg(target_os = "linux")]
+    pub fn set_pty_strip_ansi(&mut self) -> &mut Self {
+        log::debug!("setting pty to strip ANSI on command {:?}", self.display);
+        self.pty_strip_ansi = true;
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn with_pty_strip_ansi(mut self) -> Self {
+        self.set_pty_strip_ansi();
+        self
+    }
+
+    /// Opens a fresh pseudo-terminal and points stdio at it, returning the
+    /// master end and something reading everything written to it.
+    #[cfg(target_os = "linux")]
+    fn open_pty(&mut self, size: PtySize) -> Result<(PtyMaster, handle::Reader)> {
+        pty::open(size)
+            .and_then(|(master, slave)| {
+                let reader = handle::Reader::single(master.try_clone()?);
+                self.inner
+                    .stdin(slave.try_clone()?)
+                    .stdout(slave.try_clone()?)
+                    .stderr(slave);
+                Ok((PtyMaster::new(master), reader))
+            })
+            .map_err(|err| Error::new(self.display.clone(), Cause::PtyFailed(err)))
+    }
+
+    /// Run the command in a [`Sandbox`]. Setting this again replaces the
+    /// previous sandbox. The sandbox is set up after the command's fds,
+    /// rlimits, and credentials, regardless of what order they were set in, so
+    /// something like [`Command::set_uid`] picks who the command is inside the
+    /// sandbox too.
+    #[cfg(target_os = "linux")]
+    pub fn set_sandbox(&mut self, sandbox: Sandbox) -> &mut Self {
+        log::debug!(
+            "setting sandbox {:?} on command {:?}",
+            sandbox,
+            self.display
+        );
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn with_sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.set_sandbox(sandbox);
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    fn plan_sandbox(&mut self) -> Result<()> {
+        if let Some(sandbox) = self.sandbox.as_ref() {
+            // Credentials are changed before the sandbox is set up, so it's
+            // whoever we're becoming that has to be mapped into it.
+            let (uid, gid) = {
+                let credentials = self
+                    .hooks
+                    .as_ref()
+                    .and_then(|hooks| hooks.lock().ok()?.credentials.clone())
+                    .unwrap_or_default();
+                let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+                (
+                    credentials.uid.unwrap_or(uid),
+                    credentials.gid.unwrap_or(gid),
+                )
+            };
+            let planned = sandbox.plan(uid, gid).map_err(|source| {
+                Error::new(
+                    self.display.clone(),
+                    Cause::SandboxSetupFailed {
+                        step: "preparing".to_owned(),
+                        source,
+                    },
+                )
+            })?;
+            self.hooks().sandbox = Some(planned);
+        }
+        Ok(())
+    }
+
+    /// Restrict which syscalls the command can make. If this is called more
+    /// than once, then a syscall has to be allowed by every profile. The
+    /// filters are installed after everything else (including the sandbox),
+    /// right before `exec`.
+    #[cfg(target_os = "linux")]
+    pub fn add_seccomp(&mut self, profile: SeccompProfile) -> &mut Self {
+        log::debug!(
+            "adding seccomp profile {:?} to command {:?}",
+            profile.name(),
+            self.display
+        );
+        self.seccomp.push(profile);
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn with_seccomp(mut self, profile: SeccompProfile) -> Self {
+        self.add_seccomp(profile);
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    fn describe_seccomp(&self) -> String {
+        self.seccomp
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" and ")
+    }
+
+    #[cfg(unix)]
+    fn fd_failed(&self, fd: i32, source: io::Error) -> Error {
+        Error::new(self.display.clone(), Cause::FdFailed { fd, source })
+    }
+
+    #[cfg(unix)]
+    fn check_child_fd(&self, child_fd: std::os::unix::io::RawFd, instead: &str) -> Result<()> {
+        if child_fd > 2 {
+            Ok(())
+        } else {
+            Err(self.fd_failed(
+                child_fd,
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("stdio can't be passed this way; use {} instead", instead),
+                ),
+            ))
+        }
+    }
+
+    /// Pass `fd` to the command as `child_fd`, which is handy for tools that
+    /// take things like `--status-fd=3`. A copy of `fd` is made right away,
+    /// so it's fine to close yours afterwards. Stdio can't be set this way,
+    /// so `child_fd` has to be at least 3, or this fails with
+    /// [`Cause::FdFailed`].
+    #[cfg(unix)]
+    pub fn set_fd(
+        &mut self,
+        child_fd: std::os::unix::io::RawFd,
+        fd: impl std::os::unix::io::AsFd,
+    ) -> Result<&mut Self> {
+        self.check_child_fd(child_fd, "`set_stdin` and friends")?;
+        use std::os::unix::io::AsRawFd as _;
+        let fd = fd.as_fd();
+        log::debug!(
+            "passing fd {} as fd {} to command {:?}",
+            fd.as_raw_fd(),
+            child_fd,
+            self.display
+        );
+        let result = self.hooks().fds.insert(child_fd, fd);
+        result.map_err(|source| self.fd_failed(child_fd, source))?;
+        Ok(self)
+    }
+
+    #[cfg(unix)]
+    pub fn with_fd(
+        mut self,
+        child_fd: std::os::unix::io::RawFd,
+        fd: impl std::os::unix::io::AsFd,
+    ) -> Result<Self> {
+        self.set_fd(child_fd, fd)?;
+        Ok(self)
+    }
+
+    /// Give the command a fresh pipe as `child_fd`, which you can get the
+    /// other end of using [`Handle::pipe_fd`]. This is actually a Unix socket,
+    /// so it works in both directions. `child_fd` has to be at least 3, or
+    /// this fails with [`Cause::FdFailed`].
+    #[cfg(unix)]
+    pub fn set_pipe_fd(&mut self, child_fd: std::os::unix::io::RawFd) -> Result<&mut Self> {
+        self.check_child_fd(child_fd, "`set_stdin_piped` and friends")?;
+        log::debug!(
+            "passing a pipe as fd {} to command {:?}",
+            child_fd,
+            self.display
+        );
+        self.hooks().fds.insert_pipe(child_fd);
+        Ok(self)
+    }
+
+    #[cfg(unix)]
+    pub fn with_pipe_fd(mut self, child_fd: std::os::unix::io::RawFd) -> Result<Self> {
+        self.set_pipe_fd(child_fd)?;
+        Ok(self)
+    }
+
+    /// Pass `sockets` to the command the way systemd does for socket
+    /// activation: as fds 3 and up, with `LISTEN_FDS` set to how many there
+    /// are and `LISTEN_PID` set to the command's pid.
+    #[cfg(unix)]
+    pub fn set_socket_activation(
+        &mut self,
+        sockets: impl IntoIterator<Item = impl std::os::unix::io::AsFd>,
+    ) -> Result<&mut Self> {
+        let mut count = 0;
+        for (child_fd, socket) in (3..).zip(sockets) {
+            self.set_fd(child_fd, socket)?;
+            count += 1;
+        }
+        log::debug!(
+            "enabling socket activation with {} sockets on command {:?}",
+            count,
+            self.display
+        );
+        self.socket_activation = true;
+        Ok(self.add_env_var("LISTEN_FDS", count.to_string()))
+    }
+
+    #[cfg(unix)]
+    pub fn with_socket_activation(
+        mut self,
+        sockets: impl IntoIterator<Item = impl std::os::unix::io::AsFd>,
+    ) -> Result<Self> {
+        self.set_socket_activation(sockets)?;
+        Ok(self)
+    }
+
+    /// Opens the pipes passed to the child, returning our ends along with
+    /// whatever has to stay open until the child's spawned.
+    #[cfg(unix)]
+    fn open_fds(&mut self) -> Result<(fd::Pipes, Vec<fs::File>)> {
+        let hooks = match self.hooks.as_ref() {
+            Some(hooks) => Arc::clone(hooks),
+            None => return Ok(Default::default()),
+        };
+        let mut hooks = hooks
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let fds = &mut hooks.fds;
+        let pipes = fds
+            .open_pipes()
+            .map_err(|(fd, source)| self.fd_failed(fd, source))?;
+        let reserved = fds.reserve().map_err(|(fd, source)| {
+            fds.close_pipes();
+            self.fd_failed(fd, source)
+        })?;
+        Ok((pipes, reserved))
+    }
+
+    #[cfg(unix)]
+    fn close_fds(&self) {
+        if let Some(hooks) = self.hooks.as_ref() {
+            hooks
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner)
+                .fds
+                .close_pipes();
+        }
+    }
+
+    /// Forward `signals` to the command while [`Command::run_and_wait`] or
+    /// [`Command::run_and_wait_for_output`] waits on it, so that it doesn't
+    /// outlive us when we're asked to stop. If the command leads its own
+    /// process group, then the whole group gets them. Our previous handlers
+    /// are restored once it exits.
+    #[cfg(unix)]
+    pub fn set_signal_forwarding(&mut self, signals: impl IntoIterator<Item = i32>) -> &mut Self {
+        self.forwarded_signals = signals.into_iter().collect();
+        log::debug!(
+            "forwarding signals {:?} to command {:?}",
+            self.forwarded_signals,
+            self.display
+        );
+        self
+    }
+
+    #[cfg(unix)]
+    pub fn with_signal_forwarding(mut self, signals: impl IntoIterator<Item = i32>) -> Self {
+        self.set_signal_forwarding(signals);
+        self
+    }
+
+    /// Stop the command once `token` is cancelled, making waiting on it fail
+    /// with [`Cause::Cancelled`]. Every run of the command is attached to the
+    /// same token.
+    pub fn set_cancellation_token(&mut self, token: CancellationToken) -> &mut Self {
+        log::debug!("setting cancellation token on command {:?}", self.display);
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub fn with_cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.set_cancellation_token(token);
+        self
+    }
+
+    /// Fails without spawning anything if `token` has already been cancelled.
+    fn check_cancelled(&self, token: Option<&CancellationToken>) -> Result<()> {
+        if token.is_some_and(CancellationToken::is_cancelled) {
+            log::info!(
+                "not running command {:?}, since it was already cancelled",
+                self.display
+            );
+            return Err(Error::cancelled_before_spawn(
+                self.display.clone(),
+                Arc::clone(&self.redactor),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Starts catching the signals to forward, if there are any. This
+    /// happens before spawning, so there's no window where they'd kill us
+    /// instead.
+    #[cfg(unix)]
+    fn catch_signals(&self) -> Result<Option<signal::Catcher>> {
+        if self.forwarded_signals.is_empty() || self.is_dry_run() {
+            return Ok(None);
+        }
+        signal::Catcher::new(&self.forwarded_signals)
+            .map(Some)
+            .map_err(|(signal, source)| {
+                Error::new(
+                    self.display.clone(),
+                    Cause::SignalForwardingFailed { signal, source },
+                )
+            })
+    }
+
+    /// Prepares seccomp filters and socket activation, which can only be done
+    /// once everything else about the command is set.
+    #[cfg(unix)]
+    fn prepare_last(&mut self) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        let seccomp = self
+            .seccomp
+            .iter()
+            .map(SeccompProfile::compile)
+            .collect::<io::Result<Vec<_>>>()
+            .map_err(|source| {
+                Error::new(
+                    self.display.clone(),
+                    Cause::SeccompFailed {
+                        profiles: self.describe_seccomp(),
+                        source,
+                    },
+                )
+            })?;
+        let exec = if self.socket_activation {
+            let exec = fd::Exec::new(
+                self.inner.get_program(),
+                self.inner.get_args().map(ToOwned::to_owned),
+                self.env_vars(),
+            )
+            .map_err(|err| Error::new(self.display.clone(), Cause::SpawnFailed(err)))?;
+            Some(exec)
+        } else {
+            None
+        };
+        #[cfg(target_os = "linux")]
+        let needed = !seccomp.is_empty() || exec.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let needed = exec.is_some();
+        if !needed && self.hooks.is_none() {
+            return Ok(());
+        }
+        let mut hooks = self.hooks();
+        #[cfg(target_os = "linux")]
+        {
+            hooks.seccomp = seccomp;
+        }
+        hooks.exec = exec;
+        Ok(())
+    }
+
+    /// Failures in the child before `exec` come back looking like any other
+    /// spawn error, so we have to pick them out.
+    #[cfg(unix)]
+    fn explain_spawn_error(&self, err: io::Error) -> std::result::Result<Cause, io::Error> {
+        let (step, source) = match pre_exec::Step::untag(&err) {
+            Some(untagged) => untagged,
+            None => return Err(err),
+        };
+        let hooks = match self.hooks.as_ref() {
+            Some(hooks) => hooks
+                .lock()
+                .unwrap_or_else(std::sync::PoisonError::into_inner),
+            None => return Err(err),
+        };
+        let source = match hooks.credentials.as_ref() {
+            Some(credentials) => match credentials.explain(step, source) {
+                Ok(cause) => return Ok(cause),
+                Err(source) => source,
+            },
+            None => source,
+        };
+        if let pre_exec::Step::PassFd(index) = step {
+            return Ok(Cause::FdFailed {
+                fd: hooks.fds.child_fd(index).unwrap_or(-1),
+                source,
+            });
+        }
+        if let pre_exec::Step::SetRlimit(index) = step {
+            if let Some(limit) = hooks.rlimits.get(usize::from(index)) {
+                return Ok(Cause::RlimitFailed {
+                    resource: limit.resource,
+                    source,
+                });
+            }
+        }
+        #[cfg(target_os = "linux")]
+        if step == pre_exec::Step::Seccomp {
+            return Ok(Cause::SeccompFailed {
+                profiles: self.describe_seccomp(),
+                source,
+            });
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(sandbox) = self.sandbox.as_ref() {
+            return sandbox.explain(step, source);
+        }
+        Err(source)
+    }
+
+    fn is_dry_run(&self) -> bool {
+        !self.always_run && dry_run::is_dry_run()
+    }
+
     pub fn set_stdin(&mut self, cfg: impl Into<Stdio>) -> &mut Self {
         let cfg = cfg.into();
         log::debug!("setting stdin to {:?} on command {:?}", cfg, self.display);
         self.inner.stdin(cfg);
+        self.configured_stdio[0] = true;
         self
     }
 
@@ -137,6 +998,7 @@ impl Command {
         let cfg = cfg.into();
         log::debug!("setting stdout to {:?} on command {:?}", cfg, self.display);
         self.inner.stdout(cfg);
+        self.configured_stdio[1] = true;
         self
     }
 
@@ -169,6 +1031,7 @@ impl Command {
         let cfg = cfg.into();
         log::debug!("setting stderr to {:?} on command {:?}", cfg, self.display);
         self.inner.stderr(cfg);
+        self.configured_stdio[2] = true;
         self
     }
 
@@ -201,15 +1064,30 @@ impl Command {
         let key = key.as_ref();
         let val = val.as_ref();
         log::debug!(
-            "adding env var {:?} = {:?} to command {:?}",
-            key,
-            val,
+            "adding env var {:?} to command {:?}",
+            self.redact_env_var(key, val),
             self.display
         );
         self.inner.env(key, val);
         self
     }
 
+    /// The same as `add_env_var`, but the value will be replaced with
+    /// [`REDACTED`] anywhere bossy would otherwise show it.
+    pub fn add_secret_env_var(
+        &mut self,
+        key: impl AsRef<OsStr>,
+        val: impl AsRef<OsStr>,
+    ) -> &mut Self {
+        self.add_secret(val.as_ref());
+        self.add_env_var(key, val)
+    }
+
+    pub fn with_secret_env_var(mut self, key: impl AsRef<OsStr>, val: impl AsRef<OsStr>) -> Self {
+        self.add_secret_env_var(key, val);
+        self
+    }
+
     pub fn with_env_var(mut self, key: impl AsRef<OsStr>, val: impl AsRef<OsStr>) -> Self {
         self.add_env_var(key, val);
         self
@@ -233,14 +1111,269 @@ impl Command {
         self
     }
 
+    /// Load env vars from a dotenv-style file, supporting comments, quoting,
+    /// `export` prefixes, and `${VAR}` expansion against the command's
+    /// environment. If the file fails to load, then the command is left
+    /// unchanged.
+    pub fn add_env_file(&mut self, path: impl AsRef<Path>) -> Result<&mut Self> {
+        let path = path.as_ref();
+        log::debug!(
+            "loading env file {:?} into command {:?}",
+            path,
+            self.display
+        );
+        let contents = fs::read_to_string(path).map_err(|source| {
+            Error::new(
+                self.display.clone(),
+                Cause::EnvFileReadFailed {
+                    path: path.to_owned(),
+                    source,
+                },
+            )
+        })?;
+        let vars = env_file::parse(&contents, |key| {
+            self.env_var(key)
+                .map(|val| val.to_string_lossy().into_owned())
+        })
+        .map_err(|env_file::ParseError { line, message }| {
+            Error::new(
+                self.display.clone(),
+                Cause::EnvFileInvalid {
+                    path: path.to_owned(),
+                    line,
+                    message,
+                },
+            )
+        })?;
+        Ok(self.add_env_vars(vars))
+    }
+
+    pub fn with_env_file(mut self, path: impl AsRef<Path>) -> Result<Self> {
+        self.add_env_file(path)?;
+        Ok(self)
+    }
+
+    pub fn remove_env_var(&mut self, key: impl AsRef<OsStr>) -> &mut Self {
+        let key = key.as_ref();
+        log::debug!("removing env var {:?} from command {:?}", key, self.display);
+        self.inner.env_remove(key);
+        self
+    }
+
+    pub fn without_env_var(mut self, key: impl AsRef<OsStr>) -> Self {
+        self.remove_env_var(key);
+        self
+    }
+
+    /// Set the env vars that tools conventionally check for whether or not to
+    /// use color: `NO_COLOR`, `CLICOLOR_FORCE`, `CARGO_TERM_COLOR`, and
+    /// `TERM`. With [`ColorMode::Auto`], these are all left the way the parent
+    /// process has them. With [`ColorMode::Always`], `TERM` is only changed if
+    /// it's unset or `dumb`, in which case it becomes `xterm-256color`.
+    pub fn set_color(&mut self, mode: ColorMode) -> &mut Self {
+        log::debug!("setting color to {:?} on command {:?}", mode, self.display);
+        for (key, action) in mode.env_vars().iter() {
+            match action {
+                ansi::EnvAction::Set(val) => self.add_env_var(key, val),
+                ansi::EnvAction::Remove => self.remove_env_var(key),
+                ansi::EnvAction::Inherit => match env::var_os(key) {
+                    Some(val) => self.add_env_var(key, val),
+                    None => self.remove_env_var(key),
+                },
+                ansi::EnvAction::InheritOr(default) => match env::var_os(key) {
+                    Some(val) if val != "dumb" => self.add_env_var(key, val),
+                    _ => self.add_env_var(key, default),
+                },
+            };
+        }
+        self
+    }
+
+    pub fn with_color(mut self, mode: ColorMode) -> Self {
+        self.set_color(mode);
+        self
+    }
+
+    /// Copy an env var from the parent process's environment, if it's set
+    /// there. This is mainly useful for pure commands that still need things
+    /// like `PATH`, `HOME`, `TERM`, and `LANG` to function.
+    pub fn inherit_env_var(&mut self, key: impl AsRef<OsStr>) -> &mut Self {
+        let key = key.as_ref();
+        if let Some(val) = env::var_os(key) {
+            log::debug!(
+                "inheriting env var {:?} on command {:?}",
+                self.redact_env_var(key, &val),
+                self.display
+            );
+            self.inner.env(key, val);
+        } else {
+            log::debug!(
+                "not inheriting env var {:?} on command {:?}, since it isn't set",
+                key,
+                self.display
+            );
+        }
+        self
+    }
+
+    pub fn with_inherited_env_var(mut self, key: impl AsRef<OsStr>) -> Self {
+        self.inherit_env_var(key);
+        self
+    }
+
+    pub fn inherit_env_vars(
+        &mut self,
+        keys: impl IntoIterator<Item = impl AsRef<OsStr>>,
+    ) -> &mut Self {
+        for key in keys.into_iter() {
+            self.inherit_env_var(key);
+        }
+        self
+    }
+
+    pub fn with_inherited_env_vars(
+        mut self,
+        keys: impl IntoIterator<Item = impl AsRef<OsStr>>,
+    ) -> Self {
+        self.inherit_env_vars(keys);
+        self
+    }
+
+    fn join_env_path(&mut self, key: &OsStr, path: &Path, prepend: bool) {
+        const SEPARATOR: &str = if cfg!(windows) { ";" } else { ":" };
+        let val = match self.env_var(key).filter(|current| !current.is_empty()) {
+            Some(current) => {
+                let (first, second) = if prepend {
+                    (path.as_os_str(), current.as_os_str())
+                } else {
+                    (current.as_os_str(), path.as_os_str())
+                };
+                let mut val = first.to_owned();
+                val.push(SEPARATOR);
+                val.push(second);
+                val
+            }
+            None => path.as_os_str().to_owned(),
+        };
+        log::debug!(
+            "{} {:?} to env var {:?} on command {:?}, which is now {:?}",
+            if prepend { "prepending" } else { "appending" },
+            path,
+            key,
+            self.display,
+            self.redact(&val.to_string_lossy())
+        );
+        self.inner.env(key, val);
+    }
+
+    /// Add a path to the front of a `PATH`-like env var, using whatever the
+    /// command's environment already has as the rest of the value.
+    pub fn prepend_env_path(
+        &mut self,
+        key: impl AsRef<OsStr>,
+        path: impl AsRef<Path>,
+    ) -> &mut Self {
+        self.join_env_path(key.as_ref(), path.as_ref(), true);
+        self
+    }
+
+    pub fn with_prepended_env_path(
+        mut self,
+        key: impl AsRef<OsStr>,
+        path: impl AsRef<Path>,
+    ) -> Self {
+        self.prepend_env_path(key, path);
+        self
+    }
+
+    /// Add a path to the end of a `PATH`-like env var, using whatever the
+    /// command's environment already has as the rest of the value.
+    pub fn append_env_path(&mut self, key: impl AsRef<OsStr>, path: impl AsRef<Path>) -> &mut Self {
+        self.join_env_path(key.as_ref(), path.as_ref(), false);
+        self
+    }
+
+    pub fn with_appended_env_path(
+        mut self,
+        key: impl AsRef<OsStr>,
+        path: impl AsRef<Path>,
+    ) -> Self {
+        self.append_env_path(key, path);
+        self
+    }
+
+    /// Get the value an env var will have when the command runs, taking into
+    /// account both the parent environment (for impure commands) and any
+    /// changes made to the command.
+    pub fn env_var(&self, key: impl AsRef<OsStr>) -> Option<OsString> {
+        let key = key.as_ref();
+        self.inner
+            .get_envs()
+            .find(|(k, _)| *k == key)
+            .map(|(_, val)| val.map(ToOwned::to_owned))
+            .unwrap_or_else(|| if self.pure { None } else { env::var_os(key) })
+    }
+
+    /// Get the full environment the command will run with.
+    pub fn env_vars(&self) -> BTreeMap<OsString, OsString> {
+        let mut vars = if self.pure {
+            BTreeMap::new()
+        } else {
+            env::vars_os().collect()
+        };
+        for (key, val) in self.inner.get_envs() {
+            if let Some(val) = val {
+                vars.insert(key.to_owned(), val.to_owned());
+            } else {
+                vars.remove(key);
+            }
+        }
+        vars
+    }
+
     pub fn add_arg(&mut self, name: impl AsRef<OsStr>) -> &mut Self {
         let name = name.as_ref();
-        log::debug!("adding arg {:?} to command {:?}", name, self.display);
+        log::debug!(
+            "adding arg {:?} to command {:?}",
+            self.redact(&name.to_string_lossy()),
+            self.display
+        );
         self.inner.arg(name);
         self.push_display(name);
         self
     }
 
+    /// The same as `add_arg`, but the arg will be replaced with [`REDACTED`]
+    /// anywhere bossy would otherwise show it.
+    pub fn add_secret_arg(&mut self, name: impl AsRef<OsStr>) -> &mut Self {
+        self.add_secret(name.as_ref());
+        self.add_arg(name)
+    }
+
+    pub fn with_secret_arg(mut self, name: impl AsRef<OsStr>) -> Self {
+        self.add_secret_arg(name);
+        self
+    }
+
+    /// Redact anything matching `pattern` from display strings, logs, and
+    /// errors. If the pattern has a capture group, then only the first group is
+    /// redacted, so `--token=(\S+)` will leave `--token=` intact.
+    pub fn add_redaction_pattern(&mut self, pattern: Regex) -> &mut Self {
+        log::debug!(
+            "adding redaction pattern {:?} to command {:?}",
+            pattern.as_str(),
+            self.display
+        );
+        Arc::make_mut(&mut self.redactor).add_pattern(pattern);
+        self.refresh_display();
+        self
+    }
+
+    pub fn with_redaction_pattern(mut self, pattern: Regex) -> Self {
+        self.add_redaction_pattern(pattern);
+        self
+    }
+
     pub fn with_arg(mut self, name: impl AsRef<OsStr>) -> Self {
         self.add_arg(name);
         self
@@ -272,7 +1405,101 @@ impl Command {
     }
 
     fn run_inner(&mut self) -> Result<Handle> {
-        Error::from_child_result(self.display.clone(), self.inner.spawn())
+        let context = Arc::new(report::Context::new(self));
+        match self.spawn() {
+            Ok(handle) => Ok(handle.with_context(context)),
+            Err(err) => Err(err.with_context(context)),
+        }
+    }
+
+    fn spawn(&mut self) -> Result<Handle> {
+        self.check_cancelled(self.cancellation.as_ref())?;
+        let trace = Trace::new(&self.display, self.inner.get_current_dir());
+        trace.clone().in_scope(|| self.spawn_traced(trace))
+    }
+
+    fn spawn_traced(&mut self, trace: Trace) -> Result<Handle> {
+        if self.is_dry_run() {
+            log::info!("dry run, so not running command {}", self.display_escaped());
+            let output = self
+                .dry_run_output
+                .clone()
+                .unwrap_or_else(|| process::Output {
+                    status: dry_run::exit_status(0),
+                    stdout: Vec::new(),
+                    stderr: Vec::new(),
+                });
+            return Ok(Handle::fake(
+                self.display.clone(),
+                Arc::clone(&self.redactor),
+                trace,
+                output,
+            ));
+        }
+        #[cfg(target_os = "linux")]
+        let pty = match self.pty {
+            Some(size) => Some((self.open_pty(size)?, self.pty_strip_ansi)),
+            None => None,
+        };
+        #[cfg(unix)]
+        self.check_credentials()?;
+        #[cfg(target_os = "linux")]
+        self.plan_sandbox()?;
+        #[cfg(unix)]
+        self.prepare_last()?;
+        #[cfg(unix)]
+        let (pipes, reserved) = self.open_fds()?;
+        let audit = Audit::new(self);
+        let result = self.inner.spawn();
+        #[cfg(unix)]
+        {
+            drop(reserved);
+            self.close_fds();
+        }
+        #[cfg(unix)]
+        let result = match result.map_err(|err| self.explain_spawn_error(err)) {
+            Err(Ok(cause)) => {
+                let err = Error::new(self.display.clone(), cause);
+                audit.spawn_failed(&err);
+                return Err(err);
+            }
+            Err(Err(err)) => Err(err),
+            Ok(child) => Ok(child),
+        };
+        if let Err(err) = &result {
+            audit.spawn_failed(err);
+        }
+        let result = Error::from_child_result(
+            self.display.clone(),
+            Arc::clone(&self.redactor),
+            trace,
+            audit,
+            result,
+        );
+        #[cfg(target_os = "linux")]
+        let result = if self.seccomp.is_empty() {
+            result
+        } else {
+            let profiles = self.describe_seccomp();
+            result.map(|handle| handle.with_seccomp(profiles))
+        };
+        #[cfg(unix)]
+        let result = result.map(|handle| handle.with_pipes(pipes));
+        let result = match &self.cancellation {
+            Some(token) => result.map(|handle| handle.with_cancellation_token(token.clone())),
+            None => result,
+        };
+        #[cfg(target_os = "linux")]
+        if let Some(((master, reader), strip_ansi)) = pty {
+            // We need to close our copies of the slave end, or the reader
+            // will never see the child close it.
+            self.inner
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null());
+            return result.map(|handle| handle.with_pty(master, reader, strip_ansi));
+        }
+        result
     }
 
     /// Run the command and give you a delightful [`Handle`] to it. This allows
@@ -284,57 +1511,165 @@ impl Command {
         self.run_inner()
     }
 
+    /// Points any stdio that wasn't set at [`Stdio::null`], since a detached
+    /// command would otherwise hold onto ours. Log files take precedence.
+    fn set_detached_stdio(&mut self, options: &DetachOptions) -> Result<()> {
+        let (stdout, stderr) = options
+            .open_logs()
+            .map_err(|cause| Error::new(self.display.clone(), cause))?;
+        if !self.configured_stdio[0] {
+            self.set_stdin_null();
+        }
+        match stdout {
+            Some(log) => self.set_stdout(log),
+            None if !self.configured_stdio[1] => self.set_stdout_null(),
+            None => self,
+        };
+        match stderr {
+            Some(log) => self.set_stderr(log),
+            None if !self.configured_stdio[2] => self.set_stderr_null(),
+            None => self,
+        };
+        Ok(())
+    }
+
+    /// Spawns the command detached from us, returning its pid.
+    #[cfg(unix)]
+    fn detach(&mut self, pidfile: Option<&daemon::Pidfile>) -> Result<u32> {
+        let (pid_reader, pid_writer) = std::os::unix::net::UnixStream::pair()
+            .map_err(|err| Error::new(self.display.clone(), Cause::SpawnFailed(err)))?;
+        self.hooks().detach = Some(daemon::Detach {
+            pid_pipe: {
+                use std::os::unix::io::AsRawFd as _;
+                pid_writer.as_raw_fd()
+            },
+            pidfile: pidfile.map(daemon::Pidfile::as_raw_fd),
+        });
+        let result = self.run_inner();
+        self.hooks().detach = None;
+        drop(pid_writer);
+        let mut handle = result?;
+        // The record should be for the detached process, so it has to wait
+        // until we know its pid.
+        let mut audit = handle.take_audit();
+        // This only waits for the intermediate process, which exits as soon
+        // as it's forked the detached one.
+        let pid = handle.wait().and_then(|_| {
+            daemon::read_daemon_pid(pid_reader)
+                .map_err(|err| Error::new(self.display.clone(), Cause::SpawnFailed(err)))
+        });
+        match &pid {
+            Ok(pid) => {
+                audit.spawned(*pid);
+                audit.detached();
+            }
+            Err(err) => audit.spawn_failed(err),
+        }
+        pid
+    }
+
     /// Run the command and then detach it from the parent process. This allows
     /// the child process to outlive the parent process, akin to what can be
-    /// achieved using `nohup` or `setsid`. This will automatically set stdin,
-    /// stdout, and stderr to use [`Stdio::null`], so if you don't want that to
-    /// happen, then you're screwed.
+    /// achieved using `nohup` or `setsid`. Any of stdin, stdout, and stderr
+    /// that weren't set on the command are set to use [`Stdio::null`].
+    ///
+    /// On Unix, this only returns once the detached command has started, so
+    /// it still fails if the program can't be run.
     pub fn run_and_detach(&mut self) -> Result<()> {
+        self.run_and_detach_with(&DetachOptions::default())
+    }
+
+    /// The same as [`Command::run_and_detach`], but with the command's output
+    /// going where `options` says.
+    pub fn run_and_detach_with(&mut self, options: &DetachOptions) -> Result<()> {
         log::info!("running command {:?} and detaching", self.display);
-        // This is pretty much lifted from the implementation in Alacritty:
-        // https://github.com/alacritty/alacritty/blob/8bd2c13490f8cb6ad6b0c1104f9586b3554efea2/alacritty/src/daemon.rs
+        if self.is_dry_run() {
+            return self.run_inner().map(Handle::leak);
+        }
+        self.set_detached_stdio(options)?;
         #[cfg(unix)]
-        unsafe {
-            use std::os::unix::process::CommandExt as _;
-
-            let display = self.display.clone();
-            self.inner.pre_exec(move || match libc::fork() {
-                -1 => {
-                    let err = std::io::Error::last_os_error();
-                    log::error!("`fork` failed for command {:?}: {}", display, err);
-                    Err(err)
-                }
-                0 => {
-                    if libc::setsid() == -1 {
-                        let err = std::io::Error::last_os_error();
-                        log::error!("`setsid` failed for command {:?}: {}", display, err);
-                        Err(err)
-                    } else {
-                        Ok(())
-                    }
-                }
-                _ => libc::_exit(0),
-            });
+        {
+            self.detach(None).map(drop)
         }
         #[cfg(windows)]
-        unsafe {
+        {
             use std::os::windows::process::CommandExt;
             use winapi::um::winbase::{CREATE_NEW_PROCESS_GROUP, CREATE_NO_WINDOW};
 
             self.inner
                 .creation_flags(CREATE_NEW_PROCESS_GROUP | CREATE_NO_WINDOW);
+            self.run_inner().map(Handle::leak)
+        }
+    }
+
+    /// Run the command as a daemon, which is detached just like with
+    /// [`Command::run_and_detach`], but gives you a [`Daemon`] to check in on
+    /// or stop it with later.
+    #[cfg(unix)]
+    pub fn run_as_daemon(&mut self, options: &DaemonOptions) -> Result<Daemon> {
+        log::info!("running command {:?} as a daemon", self.display);
+        if self.is_dry_run() {
+            self.run_inner().map(Handle::leak)?;
+            return Ok(Daemon::fake(options.pidfile().map(ToOwned::to_owned)));
         }
-        self.set_stdin_null()
-            .set_stdout_null()
-            .set_stderr_null()
+        let pidfile = options
+            .pidfile()
+            .map(daemon::Pidfile::lock)
+            .transpose()
+            .map_err(|cause| Error::new(self.display.clone(), cause))?;
+        self.set_detached_stdio(options.detach())?;
+        let pid = self.detach(pidfile.as_ref())?;
+        log::info!(
+            "command {:?} is running as a daemon with pid {}",
+            self.display,
+            pid
+        );
+        let pidfile = pidfile
+            .map(|pidfile| pidfile.write(pid))
+            .transpose()
+            .map_err(|cause| Error::new(self.display.clone(), cause))?;
+        Ok(Daemon::new(pid, pidfile))
+    }
+
+    /// Run the command and drive it interactively with a [`Session`]. This
+    /// will automatically set stdin, stdout, and stderr to use
+    /// [`Stdio::piped`], unless the command is attached to a pseudo-terminal.
+    pub fn run_interactive(&mut self) -> Result<Session> {
+        log::info!("running command {:?} interactively", self.display);
+        // Opening a pseudo-terminal overrides all of these anyway.
+        self.set_stdin_piped()
+            .set_stdout_piped()
+            .set_stderr_piped()
             .run_inner()
-            .map(|handle| handle.leak())
+            .map(Session::new)
+    }
+
+    /// Runs the command and waits on it with `wait`, forwarding signals and
+    /// stopping it if `cancellation` is cancelled along the way.
+    fn run_and_watch<T>(
+        &mut self,
+        cancellation: Option<&CancellationToken>,
+        wait: impl FnOnce(Handle, Watch<'_>) -> Result<T>,
+    ) -> Result<T> {
+        #[cfg(unix)]
+        let mut catcher = self.catch_signals()?;
+        let mut watch = Watch::default();
+        if let Some(token) = cancellation {
+            self.check_cancelled(Some(token))?;
+            watch.cancel_on(token);
+        }
+        #[cfg(unix)]
+        if let Some(catcher) = &mut catcher {
+            watch.forward(catcher);
+        }
+        let handle = self.run_inner()?;
+        wait(handle, watch)
     }
 
     /// Run the command and block until it exits.
     pub fn run_and_wait(&mut self) -> Result<ExitStatus> {
         log::info!("running command {:?} and waiting for exit", self.display);
-        self.run_inner()?.wait()
+        self.run_and_watch(None, Handle::wait_watched)
     }
 
     /// Run the command and block until its output is collected. This will
@@ -344,8 +1679,20 @@ impl Command {
         log::info!("running command {:?} and waiting for output", self.display);
         self.set_stdout_piped()
             .set_stderr_piped()
-            .run_inner()?
-            .wait_for_output()
+            .run_and_watch(None, Handle::wait_for_output_watched)
+    }
+
+    /// The same as [`Command::run_and_wait_for_output`], but the command is
+    /// also stopped if `cancellation` is cancelled, on top of any token set on
+    /// the command itself.
+    pub(crate) fn run_and_wait_for_output_cancellable(
+        &mut self,
+        cancellation: &CancellationToken,
+    ) -> Result<Output> {
+        log::info!("running command {:?} and waiting for output", self.display);
+        self.set_stdout_piped()
+            .set_stderr_piped()
+            .run_and_watch(Some(cancellation), Handle::wait_for_output_watched)
     }
 
     pub fn run_and_wait_for_str<T>(&mut self, f: impl FnOnce(&str) -> T) -> Result<T> {
@@ -1,12 +1,17 @@
-use crate::{Error, ExitStatus};
+use crate::{ansi, redact::Redactor, Error, ExitStatus};
 use std::{
+    borrow::Cow,
     fmt::{self, Display},
     process, str,
+    sync::Arc,
 };
 
 #[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OutputStream {
+    #[cfg_attr(feature = "serde", serde(rename = "stdout"))]
     Out,
+    #[cfg_attr(feature = "serde", serde(rename = "stderr"))]
     Err,
 }
 
@@ -26,15 +31,54 @@ impl OutputStream {
 }
 
 /// All your output, in one convenient place! Wow!
-#[derive(Debug)]
 pub struct Output {
     command: String,
+    redactor: Arc<Redactor>,
     inner: process::Output,
 }
 
+/// The streams are shown as text with secrets redacted, since otherwise
+/// they'd be unreadable lists of bytes, and secrets would be in them.
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Output")
+            .field("command", &self.command)
+            .field("status", &self.status())
+            .field(
+                "stdout",
+                &self.redact(&String::from_utf8_lossy(self.stdout())),
+            )
+            .field(
+                "stderr",
+                &self.redact(&String::from_utf8_lossy(self.stderr())),
+            )
+            .finish()
+    }
+}
+
 impl Output {
-    pub(crate) fn new(command: String, inner: process::Output) -> Self {
-        Self { command, inner }
+    pub(crate) fn new(command: String, redactor: Arc<Redactor>, inner: process::Output) -> Self {
+        Self {
+            command,
+            redactor,
+            inner,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub(crate) fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        self.redactor.redact(text)
+    }
+
+    /// Stderr as it should appear in an error message, which means no escape
+    /// sequences and no secrets.
+    pub(crate) fn stderr_for_display(&self) -> String {
+        let plain = ansi::strip(self.stderr());
+        self.redact(&String::from_utf8_lossy(&plain)).into_owned()
     }
 
     pub fn status(&self) -> ExitStatus {
@@ -56,6 +100,12 @@ impl Output {
         Error::from_utf8_result(&self.command, stream, str::from_utf8(self.stream(stream)))
     }
 
+    pub(crate) fn stream_plain(&self, stream: OutputStream) -> crate::Result<String> {
+        let plain = ansi::strip(self.stream(stream));
+        Error::from_utf8_result(&self.command, stream, str::from_utf8(&plain))
+            .map(ToOwned::to_owned)
+    }
+
     pub fn stdout(&self) -> &[u8] {
         self.stream(OutputStream::Out)
     }
@@ -64,6 +114,12 @@ impl Output {
         self.stream_str(OutputStream::Out)
     }
 
+    /// The same as `stdout_str`, but with ANSI escape sequences (colors,
+    /// cursor movement, etc.) stripped out.
+    pub fn stdout_plain(&self) -> crate::Result<String> {
+        self.stream_plain(OutputStream::Out)
+    }
+
     pub fn stderr(&self) -> &[u8] {
         self.stream(OutputStream::Err)
     }
@@ -71,4 +127,10 @@ impl Output {
     pub fn stderr_str(&self) -> crate::Result<&str> {
         self.stream_str(OutputStream::Err)
     }
+
+    /// The same as `stderr_str`, but with ANSI escape sequences (colors,
+    /// cursor movement, etc.) stripped out.
+    pub fn stderr_plain(&self) -> crate::Result<String> {
+        self.stream_plain(OutputStream::Err)
+    }
 }
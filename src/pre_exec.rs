@@ -0,0 +1,151 @@
+use crate::{credentials, daemon, fd, rlimit};
+#[cfg(target_os = "linux")]
+use crate::{pty, sandbox, seccomp};
+use std::io;
+
+// If something fails in the child before `exec`, all we get back is an errno,
+// so we tag it with which step failed. Real errnos never get this high.
+const TAG: i32 = 0x7b;
+
+/// Something done in the child between `fork` and `exec` that can fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Step {
+    SetGroups,
+    SetGid,
+    SetUid,
+    Unshare,
+    /// The index is into the files written by `Sandbox`, in order.
+    WriteIdMap(u8),
+    /// The index is into the mounts done by `Sandbox`, in order.
+    Mount(u8),
+    MountProc,
+    Fork,
+    Seccomp,
+    /// The index is into the fds passed to the child, in order.
+    PassFd(u8),
+    /// The index is into the rlimits set on the child, in order.
+    SetRlimit(u8),
+}
+
+impl Step {
+    fn encode(self) -> (u8, u8) {
+        match self {
+            Self::SetGroups => (1, 0),
+            Self::SetGid => (2, 0),
+            Self::SetUid => (3, 0),
+            Self::Unshare => (4, 0),
+            Self::WriteIdMap(index) => (5, index),
+            Self::Mount(index) => (6, index),
+            Self::MountProc => (7, 0),
+            Self::Fork => (8, 0),
+            Self::Seccomp => (9, 0),
+            Self::PassFd(index) => (10, index),
+            Self::SetRlimit(index) => (11, index),
+        }
+    }
+
+    fn decode(kind: u8, index: u8) -> Option<Self> {
+        Some(match kind {
+            1 => Self::SetGroups,
+            2 => Self::SetGid,
+            3 => Self::SetUid,
+            4 => Self::Unshare,
+            5 => Self::WriteIdMap(index),
+            6 => Self::Mount(index),
+            7 => Self::MountProc,
+            8 => Self::Fork,
+            9 => Self::Seccomp,
+            10 => Self::PassFd(index),
+            11 => Self::SetRlimit(index),
+            _ => return None,
+        })
+    }
+
+    /// Tags `err` with this step. This doesn't allocate, so it's safe to use
+    /// in the child.
+    pub fn tag(self, err: io::Error) -> io::Error {
+        let errno = err.raw_os_error().unwrap_or(libc::EINVAL);
+        let (kind, index) = self.encode();
+        io::Error::from_raw_os_error(
+            (TAG << 24) | (i32::from(kind) << 16) | (i32::from(index) << 8) | (errno & 0xff),
+        )
+    }
+
+    /// Splits an error from spawning back into the step that failed and the
+    /// original error, if it was tagged.
+    pub fn untag(err: &io::Error) -> Option<(Self, io::Error)> {
+        let raw = err.raw_os_error()?;
+        if raw >> 24 != TAG {
+            return None;
+        }
+        let step = Self::decode((raw >> 16) as u8, (raw >> 8) as u8)?;
+        Some((step, io::Error::from_raw_os_error(raw & 0xff)))
+    }
+
+    /// Checks the return value of a libc function, tagging the error with
+    /// this step if it failed.
+    pub fn check(self, ret: libc::c_int) -> io::Result<libc::c_int> {
+        if ret == -1 {
+            Err(self.tag(io::Error::last_os_error()))
+        } else {
+            Ok(ret)
+        }
+    }
+}
+
+/// Everything done in the child between `fork` and `exec`. This is all one
+/// hook, so it happens in the same order no matter what order the command was
+/// set up in: the daemon is forked off first, so that everything else applies
+/// to it, and the sandbox comes after the credentials, so that the sandbox
+/// maps whoever we've become. Seccomp filters go last, since they could block
+/// anything that comes after them.
+#[derive(Debug, Default)]
+pub(crate) struct Hooks {
+    pub detach: Option<daemon::Detach>,
+    #[cfg(target_os = "linux")]
+    pub controlling_terminal: bool,
+    pub fds: fd::Fds,
+    pub rlimits: Vec<rlimit::Limit>,
+    pub credentials: Option<credentials::Credentials>,
+    #[cfg(target_os = "linux")]
+    pub sandbox: Option<sandbox::Plan>,
+    #[cfg(target_os = "linux")]
+    pub seccomp: Vec<seccomp::Filter>,
+    pub exec: Option<fd::Exec>,
+}
+
+impl Hooks {
+    pub fn credentials(&mut self) -> &mut credentials::Credentials {
+        self.credentials.get_or_insert_with(Default::default)
+    }
+
+    /// This is meant to run in the child between `fork` and `exec`, so it
+    /// can't allocate.
+    pub fn apply(&mut self) -> io::Result<()> {
+        if let Some(detach) = self.detach.as_ref() {
+            detach.apply()?;
+        }
+        #[cfg(target_os = "linux")]
+        if self.controlling_terminal {
+            pty::make_controlling_terminal()?;
+        }
+        self.fds.apply()?;
+        self.rlimits
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, limit)| limit.apply(index as u8))?;
+        if let Some(credentials) = self.credentials.as_ref() {
+            credentials.apply()?;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(plan) = self.sandbox.as_ref() {
+            plan.apply()?;
+        }
+        #[cfg(target_os = "linux")]
+        self.seccomp.iter().try_for_each(seccomp::Filter::install)?;
+        match self.exec.as_mut() {
+            Some(exec) => exec.exec(),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::{
+    ffi::CStr,
+    fs::File,
+    io::{self, Write},
+    os::unix::io::{AsRawFd as _, FromRawFd as _, RawFd},
+};
+
+/// The size of a pseudo-terminal's window, in character cells.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PtySize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+impl Default for PtySize {
+    fn default() -> Self {
+        Self { rows: 24, cols: 80 }
+    }
+}
+
+impl PtySize {
+    pub fn new(rows: u16, cols: u16) -> Self {
+        Self { rows, cols }
+    }
+}
+
+fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+fn set_size(fd: RawFd, size: PtySize) -> io::Result<()> {
+    let winsize = libc::winsize {
+        ws_row: size.rows,
+        ws_col: size.cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    check(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, &winsize) }).map(|_| ())
+}
+
+/// Opens a new pseudo-terminal, returning the master and slave ends.
+pub(crate) fn open(size: PtySize) -> io::Result<(File, File)> {
+    unsafe {
+        let master = check(libc::posix_openpt(
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        ))?;
+        let master = File::from_raw_fd(master);
+        check(libc::grantpt(master.as_raw_fd()))?;
+        check(libc::unlockpt(master.as_raw_fd()))?;
+        let mut name = [0 as libc::c_char; 128];
+        match libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) {
+            0 => (),
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
+        let slave = check(libc::open(
+            CStr::from_ptr(name.as_ptr()).as_ptr(),
+            libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+        ))?;
+        let slave = File::from_raw_fd(slave);
+        set_size(master.as_raw_fd(), size)?;
+        Ok((master, slave))
+    }
+}
+
+/// Makes the slave end of the pseudo-terminal (which must already be stdin)
+/// the controlling terminal of a new session. This is meant to run in the
+/// child between `fork` and `exec`.
+pub(crate) fn make_controlling_terminal() -> io::Result<()> {
+    unsafe {
+        check(libc::setsid())?;
+        check(libc::ioctl(0, libc::TIOCSCTTY, 0))?;
+    }
+    Ok(())
+}
+
+/// Terminals turn `\n` into `\r\n`, which nobody wants in captured output,
+/// so we turn it back. Escape sequences are also stripped if requested.
+pub(crate) fn clean_output(bytes: Vec<u8>, strip_ansi: bool) -> Vec<u8> {
+    let mut normalized = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter().peekable();
+    while let Some(&byte) = iter.next() {
+        if !(byte == b'\r' && iter.peek() == Some(&&b'\n')) {
+            normalized.push(byte);
+        }
+    }
+    if strip_ansi {
+        crate::ansi::strip(&normalized)
+    } else {
+        normalized
+    }
+}
+
+/// The parent's end of a child's pseudo-terminal. Anything written to this
+/// shows up on the child's stdin.
+#[derive(Debug)]
+pub struct PtyMaster {
+    file: File,
+}
+
+impl Write for PtyMaster {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl PtyMaster {
+    pub(crate) fn new(file: File) -> Self {
+        Self { file }
+    }
+
+    /// Change the terminal's window size, which sends `SIGWINCH` to the child.
+    pub fn resize(&self, size: PtySize) -> io::Result<()> {
+        set_size(self.file.as_raw_fd(), size)
+    }
+}
@@ -0,0 +1,63 @@
+use crate::Regex;
+use std::borrow::Cow;
+
+/// What secrets get replaced with.
+pub const REDACTED: &str = "****";
+
+/// Keeps track of what needs to be hidden from display strings, logs, and
+/// errors.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Redactor {
+    secrets: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl Redactor {
+    pub fn add_secret(&mut self, secret: String) {
+        // An empty secret would match everywhere, which helps nobody.
+        if !secret.is_empty() && !self.secrets.contains(&secret) {
+            self.secrets.push(secret);
+        }
+    }
+
+    pub fn add_pattern(&mut self, pattern: Regex) {
+        self.patterns.push(pattern);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.secrets.is_empty() && self.patterns.is_empty()
+    }
+
+    pub fn redact<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.is_empty() {
+            return Cow::Borrowed(text);
+        }
+        let mut text = Cow::Borrowed(text);
+        for secret in &self.secrets {
+            if text.contains(secret.as_str()) {
+                text = Cow::Owned(text.replace(secret.as_str(), REDACTED));
+            }
+        }
+        for pattern in &self.patterns {
+            if let Cow::Owned(redacted) =
+                pattern.replace_all(&text, |captures: &regex::Captures| {
+                    let whole = captures
+                        .get(0)
+                        .expect("developer error: match without group 0");
+                    match captures.get(1) {
+                        Some(group) => {
+                            let start = group.start() - whole.start();
+                            let end = group.end() - whole.start();
+                            let whole = whole.as_str();
+                            format!("{}{}{}", &whole[..start], REDACTED, &whole[end..])
+                        }
+                        None => REDACTED.to_owned(),
+                    }
+                })
+            {
+                text = Cow::Owned(redacted);
+            }
+        }
+        text
+    }
+}
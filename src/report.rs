@@ -0,0 +1,318 @@
+use crate::{Cause, Command, Error, ExitStatus};
+use std::{
+    env,
+    fmt::{self, Display},
+    io,
+    path::PathBuf,
+};
+
+/// What we knew about a command when it ran, so that errors can say more
+/// than just its name.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct Context {
+    escaped: String,
+    cwd: Option<PathBuf>,
+    // Only the env vars set (or removed, if `None`) on the command, with
+    // secrets redacted.
+    env: Vec<(String, Option<String>)>,
+    env_cleared: bool,
+}
+
+impl Context {
+    pub fn new(command: &Command) -> Self {
+        Self {
+            escaped: command.display_escaped(),
+            cwd: command
+                .inner
+                .get_current_dir()
+                .map(ToOwned::to_owned)
+                .or_else(|| env::current_dir().ok()),
+            env: command
+                .inner
+                .get_envs()
+                .map(|(key, val)| {
+                    (
+                        key.to_string_lossy().into_owned(),
+                        val.map(|val| command.redact(&val.to_string_lossy()).into_owned()),
+                    )
+                })
+                .collect(),
+            env_cleared: command.pure,
+        }
+    }
+}
+
+#[cfg(unix)]
+fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        libc::SIGHUP => "SIGHUP",
+        libc::SIGINT => "SIGINT",
+        libc::SIGQUIT => "SIGQUIT",
+        libc::SIGILL => "SIGILL",
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGPIPE => "SIGPIPE",
+        libc::SIGALRM => "SIGALRM",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGXCPU => "SIGXCPU",
+        libc::SIGXFSZ => "SIGXFSZ",
+        libc::SIGSYS => "SIGSYS",
+        _ => return None,
+    })
+}
+
+fn describe_status(status: ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exited with code {}", code);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+        if let Some(signal) = status.signal() {
+            let mut description = format!("killed by signal {}", signal);
+            if let Some(name) = signal_name(signal) {
+                description.push_str(&format!(" ({})", name));
+            }
+            if status.core_dumped() {
+                description.push_str(", core dumped");
+            }
+            return description;
+        }
+    }
+    "exited without an exit code".to_owned()
+}
+
+/// Suggestions for the usual suspects.
+fn hints(error: &Error) -> Vec<String> {
+    let mut hints = Vec::new();
+    let program = error.command().split(' ').next().unwrap_or_default();
+    let cleared = error.context().is_some_and(|context| context.env_cleared);
+    match error.cause() {
+        Cause::SpawnFailed(err) if err.kind() == io::ErrorKind::NotFound => {
+            hints.push(format!(
+                "{:?} wasn't found; make sure it's installed and on your `PATH`.",
+                program
+            ));
+            if cleared {
+                hints.push(
+                    "The command's environment was cleared, so `PATH` has to be set on it too."
+                        .to_owned(),
+                );
+            }
+        }
+        Cause::SpawnFailed(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+            hints.push(format!(
+                "{:?} isn't executable; check its permissions (`chmod +x`) and that its filesystem isn't mounted `noexec`.",
+                program
+            ));
+        }
+        _ => (),
+    }
+    match error.code() {
+        Some(127) => hints.push(
+            "Exit code 127 usually means a shell couldn't find a command it was asked to run."
+                .to_owned(),
+        ),
+        Some(126) => hints.push(
+            "Exit code 126 usually means a shell found a command, but couldn't run it.".to_owned(),
+        ),
+        _ => (),
+    }
+    #[cfg(unix)]
+    if let Some(signal) = error.signal() {
+        if let Some(resource) = error.exceeded_rlimit() {
+            hints.push(format!(
+                "The command was killed for exceeding its {} limit.",
+                resource.description()
+            ));
+        } else {
+            match signal {
+                libc::SIGKILL => hints.push(
+                    "`SIGKILL` can't be caught, so this was probably a timeout, someone killing it, or the out-of-memory killer."
+                        .to_owned(),
+                ),
+                libc::SIGSEGV | libc::SIGBUS | libc::SIGILL | libc::SIGFPE | libc::SIGABRT => {
+                    hints.push("The command crashed.".to_owned())
+                }
+                libc::SIGPIPE => hints.push(
+                    "The command wrote to a pipe that nobody was reading anymore.".to_owned(),
+                ),
+                _ => (),
+            }
+        }
+    }
+    hints
+}
+
+/// A multi-line report of an [`Error`], for showing to humans. See
+/// [`Error::report`].
+#[derive(Debug)]
+pub struct Report<'a> {
+    error: &'a Error,
+    stderr_lines: usize,
+}
+
+impl<'a> Report<'a> {
+    /// How many lines from the end of stderr to show. This defaults to 10.
+    pub fn set_stderr_lines(&mut self, lines: usize) -> &mut Self {
+        self.stderr_lines = lines;
+        self
+    }
+
+    pub fn with_stderr_lines(mut self, lines: usize) -> Self {
+        self.set_stderr_lines(lines);
+        self
+    }
+
+    fn fmt_headline(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let command = self.error.command();
+        match self.error.cause() {
+            Cause::CommandFailed(_) | Cause::CommandFailedWithOutput(_) => {
+                write!(f, "Command {:?} didn't complete successfully.", command)
+            }
+            Cause::Cancelled(_) => write!(f, "Command {:?} was cancelled.", command),
+            _ => self.error.fmt_cause(f),
+        }
+    }
+
+    fn fmt_details(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The labels are right-aligned to the longest one.
+        const WIDTH: usize = 7;
+
+        let mut details = Vec::new();
+        match self.error.context() {
+            Some(context) => {
+                details.push(("command", context.escaped.clone()));
+                if let Some(cwd) = &context.cwd {
+                    details.push(("cwd", cwd.display().to_string()));
+                }
+                for (key, val) in &context.env {
+                    details.push((
+                        "env",
+                        match val {
+                            Some(val) => format!("{}={}", key, val),
+                            None => format!("{} (removed)", key),
+                        },
+                    ));
+                }
+                if context.env_cleared {
+                    details.push((
+                        "env",
+                        if context.env.is_empty() {
+                            "(cleared)"
+                        } else {
+                            "(everything else cleared)"
+                        }
+                        .to_owned(),
+                    ));
+                }
+            }
+            None => details.push(("command", self.error.command().to_owned())),
+        }
+        if let Some(status) = self.error.status() {
+            details.push(("status", describe_status(status)));
+        }
+        let stderr = self
+            .error
+            .output()
+            .map(|output| output.stderr_for_display());
+        let lines = stderr
+            .as_deref()
+            .map(|stderr| stderr.lines().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let shown = lines.len().min(self.stderr_lines);
+        let mut last_label = "";
+        for (i, (label, value)) in details.iter().enumerate() {
+            let corner = if i == 0 { "┌" } else { "│" };
+            // Repeated labels (like `env`) are only shown once.
+            let label = if *label == last_label { "" } else { label };
+            last_label = details[i].0;
+            let colon = if label.is_empty() { ' ' } else { ':' };
+            writeln!(
+                f,
+                "  {} {:>width$}{} {}",
+                corner,
+                label,
+                colon,
+                value,
+                width = WIDTH
+            )?;
+        }
+        if shown > 0 {
+            if shown < lines.len() {
+                writeln!(f, "  ├ stderr (last {} of {} lines)", shown, lines.len())?;
+            } else {
+                writeln!(f, "  ├ stderr")?;
+            }
+            let first = lines.len() - shown;
+            let number_width = lines.len().to_string().len();
+            for (i, line) in lines[first..].iter().enumerate() {
+                writeln!(
+                    f,
+                    "  │ {:>width$} │ {}",
+                    first + i + 1,
+                    line,
+                    width = number_width
+                )?;
+            }
+        } else if stderr.is_some() {
+            writeln!(f, "  ├ stderr was empty")?;
+        }
+        writeln!(f, "  └")
+    }
+}
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error: ")?;
+        self.fmt_headline(f)?;
+        writeln!(f)?;
+        self.fmt_details(f)?;
+        if let Some(note) = self.error.note() {
+            writeln!(f, "  note: {}", note)?;
+        }
+        for hint in hints(self.error) {
+            writeln!(f, "  help: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error {
+    /// A multi-line report of what went wrong, for showing to humans: the
+    /// command (quoted so it can be pasted into a shell), its cwd and env,
+    /// how it exited, the end of its stderr, and some hints if we've got any.
+    pub fn report(&self) -> Report<'_> {
+        Report {
+            error: self,
+            stderr_lines: 10,
+        }
+    }
+}
+
+#[cfg(feature = "miette")]
+impl miette::Diagnostic for Error {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let code = match self.cause() {
+            Cause::SpawnFailed(_) => "spawn_failed",
+            Cause::WaitFailed(_) => "wait_failed",
+            Cause::CommandFailed(_) | Cause::CommandFailedWithOutput(_) => "command_failed",
+            Cause::Cancelled(_) => "cancelled",
+            _ => return None,
+        };
+        Some(Box::new(format!("bossy::{}", code)))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        let hints = hints(self);
+        if hints.is_empty() {
+            None
+        } else {
+            Some(Box::new(hints.join("\n")))
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::pre_exec::Step;
+use std::io;
+
+/// A resource that can be limited with
+/// [`Command::set_rlimit`](crate::Command::set_rlimit).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Resource {
+    /// The size of the process's virtual memory, in bytes. Allocations past
+    /// this fail, which most programs don't survive.
+    AddressSpace,
+    /// CPU time, in seconds. Going over the soft limit gets the process
+    /// `SIGXCPU`, and going over the hard limit gets it `SIGKILL`.
+    CpuTime,
+    /// The size of any one file the process writes, in bytes. Going over this
+    /// gets the process `SIGXFSZ`.
+    FileSize,
+    /// How many file descriptors the process can have open at once.
+    OpenFiles,
+    /// How many processes the user can have running at once. Note that this
+    /// counts all of the user's processes, not just this one's children.
+    Processes,
+    /// The size of core dumps, in bytes. `0` disables them entirely.
+    CoreSize,
+}
+
+impl Resource {
+    /// The resource whose limit results in the process getting `signal`.
+    pub(crate) fn from_signal(signal: libc::c_int) -> Option<Self> {
+        match signal {
+            libc::SIGXCPU => Some(Self::CpuTime),
+            libc::SIGXFSZ => Some(Self::FileSize),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            Self::AddressSpace => "address space",
+            Self::CpuTime => "CPU time",
+            Self::FileSize => "file size",
+            Self::OpenFiles => "open files",
+            Self::Processes => "processes",
+            Self::CoreSize => "core size",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Limit {
+    pub resource: Resource,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+impl Limit {
+    /// This is meant to run in the child between `fork` and `exec`, so it
+    /// can't allocate. Errors are tagged with `index`, so we can tell which
+    /// limit it was.
+    pub fn apply(self, index: u8) -> io::Result<()> {
+        fn raw(limit: u64) -> libc::rlim_t {
+            if limit == u64::MAX {
+                libc::RLIM_INFINITY
+            } else {
+                limit as libc::rlim_t
+            }
+        }
+
+        let rlimit = libc::rlimit {
+            rlim_cur: raw(self.soft),
+            rlim_max: raw(self.hard),
+        };
+        // The type of these constants varies by platform, so we just let
+        // inference deal with it.
+        let resource = match self.resource {
+            Resource::AddressSpace => libc::RLIMIT_AS,
+            Resource::CpuTime => libc::RLIMIT_CPU,
+            Resource::FileSize => libc::RLIMIT_FSIZE,
+            Resource::OpenFiles => libc::RLIMIT_NOFILE,
+            Resource::Processes => libc::RLIMIT_NPROC,
+            Resource::CoreSize => libc::RLIMIT_CORE,
+        };
+        Step::SetRlimit(index)
+            .check(unsafe { libc::setrlimit(resource, &rlimit) })
+            .map(drop)
+    }
+}
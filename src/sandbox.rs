@@ -0,0 +1,525 @@
+use crate::{pre_exec::Step, Cause};
+use std::{
+    ffi::CString,
+    io,
+    os::unix::ffi::OsStrExt as _,
+    path::{Path, PathBuf},
+    ptr,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Mount {
+    Bind {
+        src: PathBuf,
+        dst: PathBuf,
+        read_only: bool,
+    },
+    Tmpfs {
+        dst: PathBuf,
+    },
+}
+
+/// Runs a command in its own Linux namespaces, for
+/// [`Command::set_sandbox`](crate::Command::set_sandbox).
+///
+/// A sandbox always gets a fresh user namespace, which is what lets all of
+/// this work without root. The command keeps our uid and gid inside it.
+/// Everything else is opt-in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sandbox {
+    no_network: bool,
+    pid_namespace: bool,
+    mounts: Vec<Mount>,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give the command its own network namespace, which has nothing in it
+    /// but a loopback interface that's down. So no network at all, not even
+    /// `localhost`.
+    pub fn set_no_network(&mut self) -> &mut Self {
+        self.no_network = true;
+        self
+    }
+
+    pub fn with_no_network(mut self) -> Self {
+        self.set_no_network();
+        self
+    }
+
+    /// Give the command its own PID namespace, where it'll be PID 1 and can't
+    /// see any processes but its own descendants. A fresh `/proc` is mounted
+    /// to match.
+    ///
+    /// Note that PID 1 doesn't get the default behavior for signals it has no
+    /// handler for, so things like `SIGTERM` might be ignored.
+    pub fn set_pid_namespace(&mut self) -> &mut Self {
+        self.pid_namespace = true;
+        self
+    }
+
+    pub fn with_pid_namespace(mut self) -> Self {
+        self.set_pid_namespace();
+        self
+    }
+
+    /// Make `src` also show up at `dst` for the command. `dst` needs to
+    /// already exist.
+    pub fn add_bind(&mut self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> &mut Self {
+        self.mounts.push(Mount::Bind {
+            src: src.as_ref().to_owned(),
+            dst: dst.as_ref().to_owned(),
+            read_only: false,
+        });
+        self
+    }
+
+    pub fn with_bind(mut self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Self {
+        self.add_bind(src, dst);
+        self
+    }
+
+    /// The same as `add_bind`, but the command can't write to it. Passing the
+    /// same path twice makes an existing directory read-only.
+    pub fn add_read_only_bind(
+        &mut self,
+        src: impl AsRef<Path>,
+        dst: impl AsRef<Path>,
+    ) -> &mut Self {
+        self.mounts.push(Mount::Bind {
+            src: src.as_ref().to_owned(),
+            dst: dst.as_ref().to_owned(),
+            read_only: true,
+        });
+        self
+    }
+
+    pub fn with_read_only_bind(mut self, src: impl AsRef<Path>, dst: impl AsRef<Path>) -> Self {
+        self.add_read_only_bind(src, dst);
+        self
+    }
+
+    /// Mount an empty, private tmpfs at `dst`, which needs to already exist.
+    pub fn add_tmpfs(&mut self, dst: impl AsRef<Path>) -> &mut Self {
+        self.mounts.push(Mount::Tmpfs {
+            dst: dst.as_ref().to_owned(),
+        });
+        self
+    }
+
+    pub fn with_tmpfs(mut self, dst: impl AsRef<Path>) -> Self {
+        self.add_tmpfs(dst);
+        self
+    }
+
+    /// Shorthand for `add_tmpfs("/tmp")`.
+    pub fn set_private_tmp(&mut self) -> &mut Self {
+        self.add_tmpfs("/tmp")
+    }
+
+    pub fn with_private_tmp(mut self) -> Self {
+        self.set_private_tmp();
+        self
+    }
+
+    fn needs_mount_namespace(&self) -> bool {
+        !self.mounts.is_empty() || self.pid_namespace
+    }
+
+    /// Does everything that allocates up front, since the child can't. `uid`
+    /// and `gid` are who the command will be when the sandbox is set up.
+    pub(crate) fn plan(&self, uid: libc::uid_t, gid: libc::gid_t) -> io::Result<Plan> {
+        fn c_path(path: &Path) -> io::Result<CString> {
+            CString::new(path.as_os_str().as_bytes()).map_err(Into::into)
+        }
+
+        if self.mounts.len() >= usize::from(u8::MAX) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sandbox has too many mounts",
+            ));
+        }
+        let mut flags = libc::CLONE_NEWUSER;
+        if self.needs_mount_namespace() {
+            flags |= libc::CLONE_NEWNS;
+        }
+        if self.no_network {
+            flags |= libc::CLONE_NEWNET;
+        }
+        if self.pid_namespace {
+            flags |= libc::CLONE_NEWPID;
+        }
+        let mut mounts = Vec::new();
+        if self.needs_mount_namespace() {
+            // Otherwise, our mounts could propagate back out.
+            mounts.push(PlannedMount::MakePrivate);
+        }
+        for mount in &self.mounts {
+            mounts.push(match mount {
+                Mount::Bind {
+                    src,
+                    dst,
+                    read_only,
+                } => PlannedMount::Bind {
+                    src: c_path(src)?,
+                    dst: c_path(dst)?,
+                    read_only: *read_only,
+                },
+                Mount::Tmpfs { dst } => PlannedMount::Tmpfs { dst: c_path(dst)? },
+            });
+        }
+        Ok(Plan {
+            flags,
+            id_maps: [
+                (
+                    &b"/proc/self/uid_map\0"[..],
+                    format!("{} {} 1\n", uid, uid).into_bytes(),
+                ),
+                // Unprivileged users aren't allowed to write `gid_map` until
+                // `setgroups` is disabled.
+                (&b"/proc/self/setgroups\0"[..], b"deny".to_vec()),
+                (
+                    &b"/proc/self/gid_map\0"[..],
+                    format!("{} {} 1\n", gid, gid).into_bytes(),
+                ),
+            ],
+            mounts,
+            pid_namespace: self.pid_namespace,
+        })
+    }
+
+    /// A more helpful [`Cause`] for a failed step, if it was one of ours.
+    pub(crate) fn explain(&self, step: Step, source: io::Error) -> Result<Cause, io::Error> {
+        let step = match step {
+            Step::Unshare => return Ok(Cause::SandboxUnavailable(source)),
+            Step::WriteIdMap(0) => "writing uid_map".to_owned(),
+            Step::WriteIdMap(1) => "disabling setgroups".to_owned(),
+            Step::WriteIdMap(_) => "writing gid_map".to_owned(),
+            Step::Mount(0) => "making mounts private".to_owned(),
+            Step::Mount(index) => match self.mounts.get(usize::from(index) - 1) {
+                Some(Mount::Bind {
+                    src,
+                    dst,
+                    read_only,
+                }) => format!(
+                    "{}bind-mounting {:?} at {:?}",
+                    if *read_only { "read-only " } else { "" },
+                    src,
+                    dst
+                ),
+                Some(Mount::Tmpfs { dst }) => format!("mounting tmpfs at {:?}", dst),
+                None => return Err(source),
+            },
+            Step::MountProc => "mounting /proc".to_owned(),
+            Step::Fork => "forking PID 1".to_owned(),
+            _ => return Err(source),
+        };
+        Ok(Cause::SandboxSetupFailed { step, source })
+    }
+}
+
+#[derive(Debug)]
+enum PlannedMount {
+    MakePrivate,
+    Bind {
+        src: CString,
+        dst: CString,
+        read_only: bool,
+    },
+    Tmpfs {
+        dst: CString,
+    },
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl PlannedMount {
+    unsafe fn apply(&self) -> io::Result<()> {
+        match self {
+            Self::MakePrivate => check(libc::mount(
+                ptr::null(),
+                b"/\0".as_ptr() as *const _,
+                ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                ptr::null(),
+            )),
+            Self::Bind {
+                src,
+                dst,
+                read_only,
+            } => {
+                check(libc::mount(
+                    src.as_ptr(),
+                    dst.as_ptr(),
+                    ptr::null(),
+                    libc::MS_BIND | libc::MS_REC,
+                    ptr::null(),
+                ))?;
+                if *read_only {
+                    // Inside a user namespace, we aren't allowed to clear any
+                    // of these flags, so we need to keep whatever's there.
+                    let mut stat = std::mem::zeroed::<libc::statvfs>();
+                    check(libc::statvfs(dst.as_ptr(), &mut stat))?;
+                    let mut flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+                    for (st, ms) in [
+                        (libc::ST_NOSUID, libc::MS_NOSUID),
+                        (libc::ST_NODEV, libc::MS_NODEV),
+                        (libc::ST_NOEXEC, libc::MS_NOEXEC),
+                        (libc::ST_NOATIME, libc::MS_NOATIME),
+                        (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+                        (libc::ST_RELATIME, libc::MS_RELATIME),
+                    ] {
+                        if stat.f_flag & st != 0 {
+                            flags |= ms;
+                        }
+                    }
+                    check(libc::mount(
+                        ptr::null(),
+                        dst.as_ptr(),
+                        ptr::null(),
+                        flags,
+                        ptr::null(),
+                    ))?;
+                }
+                Ok(())
+            }
+            Self::Tmpfs { dst } => check(libc::mount(
+                b"tmpfs\0".as_ptr() as *const _,
+                dst.as_ptr(),
+                b"tmpfs\0".as_ptr() as *const _,
+                libc::MS_NOSUID | libc::MS_NODEV,
+                ptr::null(),
+            )),
+        }
+    }
+}
+
+unsafe fn write_file(path: &[u8], contents: &[u8]) -> io::Result<()> {
+    let fd = libc::open(path.as_ptr() as *const _, libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let written = libc::write(fd, contents.as_ptr() as *const _, contents.len());
+    let err = io::Error::last_os_error();
+    libc::close(fd);
+    if written == -1 {
+        Err(err)
+    } else {
+        Ok(())
+    }
+}
+
+// The PID of the command, as seen by the process waiting on it. This is only
+// ever set in that process, which is single-threaded.
+static SANDBOXED_PID: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pid = SANDBOXED_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+/// Waits for the command to exit, and then exits the same way. This is what
+/// our caller sees as the child, so we pass signals along too.
+unsafe fn wait_and_exit_like(pid: libc::pid_t) -> ! {
+    SANDBOXED_PID.store(pid, Ordering::SeqCst);
+    for signal in [
+        libc::SIGHUP,
+        libc::SIGINT,
+        libc::SIGQUIT,
+        libc::SIGTERM,
+        libc::SIGUSR1,
+        libc::SIGUSR2,
+        libc::SIGWINCH,
+    ] {
+        let mut action = std::mem::zeroed::<libc::sigaction>();
+        action.sa_sigaction = forward_signal as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigaction(signal, &action, ptr::null_mut());
+    }
+    // Our caller won't see that the command started until every copy of the
+    // pipe `std` uses to report `exec` failures is closed, so we can't hold
+    // onto ours.
+    if libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) == -1 {
+        for fd in 3..1024 {
+            libc::close(fd);
+        }
+    }
+    let mut status = 0;
+    while libc::waitpid(pid, &mut status, 0) == -1 {
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            libc::_exit(127);
+        }
+    }
+    if libc::WIFSIGNALED(status) {
+        let signal = libc::WTERMSIG(status);
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal);
+    }
+    libc::_exit(libc::WEXITSTATUS(status))
+}
+
+#[derive(Debug)]
+pub(crate) struct Plan {
+    flags: libc::c_int,
+    id_maps: [(&'static [u8], Vec<u8>); 3],
+    mounts: Vec<PlannedMount>,
+    pid_namespace: bool,
+}
+
+impl Plan {
+    /// This is meant to run in the child between `fork` and `exec`, so it
+    /// can't allocate.
+    pub fn apply(&self) -> io::Result<()> {
+        unsafe {
+            Step::Unshare.check(libc::unshare(self.flags))?;
+            // Changing credentials makes us undumpable, which leaves
+            // `/proc/self` owned by root, so we couldn't write the id maps.
+            // We're only dumpable again for as long as that takes.
+            let dumpable = libc::prctl(libc::PR_GET_DUMPABLE);
+            if dumpable == 0 {
+                libc::prctl(libc::PR_SET_DUMPABLE, 1);
+            }
+            for (index, (path, contents)) in self.id_maps.iter().enumerate() {
+                write_file(path, contents).map_err(|err| Step::WriteIdMap(index as u8).tag(err))?;
+            }
+            if dumpable == 0 {
+                libc::prctl(libc::PR_SET_DUMPABLE, 0);
+            }
+            for (index, mount) in self.mounts.iter().enumerate() {
+                mount
+                    .apply()
+                    .map_err(|err| Step::Mount(index as u8).tag(err))?;
+            }
+            if self.pid_namespace {
+                // Only our children end up in the new PID namespace, so the
+                // command has to be one.
+                match Step::Fork.check(libc::fork())? {
+                    0 => {
+                        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+                        Step::MountProc.check(libc::mount(
+                            b"proc\0".as_ptr() as *const _,
+                            b"/proc\0".as_ptr() as *const _,
+                            b"proc\0".as_ptr() as *const _,
+                            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                            ptr::null(),
+                        ))?;
+                    }
+                    pid => wait_and_exit_like(pid),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Command;
+    use std::{env, net::TcpListener, net::TcpStream};
+
+    // Set to what `inside_sandbox` should check, so that it only does
+    // anything when one of the other tests runs it.
+    const CHECK_VAR: &str = "BOSSY_SANDBOX_TEST_CHECK";
+
+    /// Runs `inside_sandbox` in a sandbox, unless sandboxes aren't available
+    /// here.
+    fn check_inside(sandbox: Sandbox, check: &str) {
+        let result = Command::impure(env::current_exe().unwrap())
+            .with_args([
+                "--exact",
+                "sandbox::tests::inside_sandbox",
+                "--ignored",
+                "--test-threads=1",
+            ])
+            .with_env_var(CHECK_VAR, check)
+            .with_sandbox(sandbox)
+            .run_and_wait_for_output();
+        match result {
+            Ok(_) => (),
+            Err(err) if matches!(err.cause(), Cause::SandboxUnavailable(_)) => {
+                eprintln!("skipping, since sandboxes aren't available: {}", err)
+            }
+            Err(err) => panic!("check {:?} failed in sandbox: {}", check, err),
+        }
+    }
+
+    #[test]
+    #[ignore = "this is run inside a sandbox by the other tests"]
+    fn inside_sandbox() {
+        let check = match env::var(CHECK_VAR) {
+            Ok(check) => check,
+            Err(_) => return,
+        };
+        match check.split_once(':') {
+            None if check == "pid" => assert_eq!(unsafe { libc::getpid() }, 1),
+            Some(("connect", addr)) => {
+                let err = TcpStream::connect(addr).expect_err("connected from inside the sandbox");
+                assert_eq!(err.raw_os_error(), Some(libc::ENETUNREACH), "{}", err);
+            }
+            _ => panic!("unknown check {:?}", check),
+        }
+    }
+
+    #[test]
+    fn pid_namespace_makes_the_command_pid_1() {
+        check_inside(Sandbox::new().with_pid_namespace(), "pid");
+    }
+
+    #[test]
+    fn no_network_makes_the_network_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Make sure it's the sandbox that makes this fail.
+        TcpStream::connect(addr).unwrap();
+        check_inside(
+            Sandbox::new().with_no_network(),
+            &format!("connect:{}", addr),
+        );
+    }
+
+    #[test]
+    fn credentials_are_set_before_the_sandbox_either_way() {
+        if unsafe { libc::getuid() } != 0 {
+            eprintln!("skipping, since changing uid needs root");
+            return;
+        }
+        for uid_first in [true, false] {
+            let mut command = Command::impure("id").with_arg("-u");
+            if uid_first {
+                command
+                    .set_uid(65534)
+                    .set_gid(65534)
+                    .set_sandbox(Sandbox::new());
+            } else {
+                command
+                    .set_sandbox(Sandbox::new())
+                    .set_uid(65534)
+                    .set_gid(65534);
+            }
+            match command.run_and_wait_for_output() {
+                Ok(output) => assert_eq!(output.stdout_str().unwrap().trim(), "65534"),
+                Err(err) if matches!(err.cause(), Cause::SandboxUnavailable(_)) => {
+                    eprintln!("skipping, since sandboxes aren't available: {}", err);
+                    return;
+                }
+                Err(err) => panic!("uid_first: {}: {}", uid_first, err),
+            }
+        }
+    }
+}
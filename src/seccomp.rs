@@ -0,0 +1,302 @@
+use crate::pre_exec::Step;
+use std::{
+    fmt::{self, Display},
+    io,
+};
+
+// libc's BPF constants have different types on different targets, so we just
+// define our own. These are the instruction classes and modes OR'd together.
+const BPF_LD_W_ABS: u16 = 0x20; // BPF_LD | BPF_W | BPF_ABS
+const BPF_JMP_JEQ_K: u16 = 0x15; // BPF_JMP | BPF_JEQ | BPF_K
+const BPF_JMP_JGE_K: u16 = 0x35; // BPF_JMP | BPF_JGE | BPF_K
+const BPF_JMP_JSET_K: u16 = 0x45; // BPF_JMP | BPF_JSET | BPF_K
+const BPF_RET_K: u16 = 0x06; // BPF_RET | BPF_K
+
+// Offsets into `struct seccomp_data`.
+const OFFSET_NR: u32 = 0;
+const OFFSET_ARCH: u32 = 4;
+// We only ever look at the low 32 bits of the first arg.
+#[cfg(target_endian = "little")]
+const OFFSET_ARG0: u32 = 16;
+#[cfg(target_endian = "big")]
+const OFFSET_ARG0: u32 = 20;
+
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+// On x86_64, the x32 ABI's syscalls have this bit set, and would otherwise
+// sneak right past us.
+#[cfg(target_arch = "x86_64")]
+const X32_SYSCALL_BIT: Option<u32> = Some(0x4000_0000);
+#[cfg(not(target_arch = "x86_64"))]
+const X32_SYSCALL_BIT: Option<u32> = None;
+
+/// When a syscall is still allowed despite being listed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Exception {
+    /// The first arg is exactly this.
+    Arg0Is(u32),
+    /// The first arg has all of these bits set.
+    Arg0HasBits(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Rule {
+    syscall: libc::c_long,
+    exception: Option<Exception>,
+    // Overrides what happens when this rule denies something.
+    errno: Option<i32>,
+}
+
+impl Rule {
+    fn new(syscall: libc::c_long) -> Self {
+        Self {
+            syscall,
+            exception: None,
+            errno: None,
+        }
+    }
+}
+
+/// A seccomp filter restricting which syscalls a command can make, for
+/// [`Command::add_seccomp`](crate::Command::add_seccomp). Syscalls are
+/// given by number, like `libc::SYS_ptrace`.
+///
+/// By default, a denied syscall fails with `EPERM`, which the command may or
+/// may not handle gracefully. With [`SeccompProfile::set_kill_on_violation`],
+/// the command is killed with `SIGSYS` instead, which is harder to miss.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SeccompProfile {
+    name: String,
+    description: String,
+    // If this is true, then only the listed syscalls are allowed. Otherwise,
+    // only the listed syscalls are denied.
+    allow_list: bool,
+    rules: Vec<Rule>,
+    kill: bool,
+}
+
+impl Display for SeccompProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seccomp profile {:?}, which {}",
+            self.name, self.description
+        )
+    }
+}
+
+impl SeccompProfile {
+    /// Allow every syscall but these.
+    pub fn deny_list(
+        name: impl Into<String>,
+        syscalls: impl IntoIterator<Item = libc::c_long>,
+    ) -> Self {
+        let rules = syscalls.into_iter().map(Rule::new).collect::<Vec<_>>();
+        Self {
+            name: name.into(),
+            description: format!("denies {} syscalls", rules.len()),
+            allow_list: false,
+            rules,
+            kill: false,
+        }
+    }
+
+    /// Deny every syscall but these. `execve` is always allowed, since
+    /// otherwise the command couldn't even start, along with what's needed to
+    /// report it if it fails to.
+    pub fn allow_list(
+        name: impl Into<String>,
+        syscalls: impl IntoIterator<Item = libc::c_long>,
+    ) -> Self {
+        let mut rules = syscalls.into_iter().map(Rule::new).collect::<Vec<_>>();
+        let description = format!("only allows {} syscalls", rules.len());
+        rules.extend(
+            [
+                libc::SYS_execve,
+                libc::SYS_write,
+                libc::SYS_exit,
+                libc::SYS_exit_group,
+            ]
+            .iter()
+            .map(|syscall| Rule::new(*syscall)),
+        );
+        Self {
+            name: name.into(),
+            description,
+            allow_list: true,
+            rules,
+            kill: false,
+        }
+    }
+
+    /// Deny creating any sockets but Unix sockets.
+    pub fn no_network() -> Self {
+        let mut profile = Self::deny_list("no-network", [libc::SYS_socket, libc::SYS_socketpair]);
+        for rule in &mut profile.rules {
+            rule.exception = Some(Exception::Arg0Is(libc::AF_UNIX as u32));
+        }
+        profile.description = "denies creating sockets other than Unix sockets".to_owned();
+        profile
+    }
+
+    /// Deny tracing other processes or reading and writing their memory.
+    pub fn no_ptrace() -> Self {
+        let mut profile = Self::deny_list(
+            "no-ptrace",
+            [
+                libc::SYS_ptrace,
+                libc::SYS_process_vm_readv,
+                libc::SYS_process_vm_writev,
+            ],
+        );
+        profile.description = "denies tracing other processes".to_owned();
+        profile
+    }
+
+    /// Deny creating child processes, so the command can't run anything
+    /// else. Threads are still allowed.
+    pub fn no_exec_children() -> Self {
+        let mut rules = vec![Rule {
+            exception: Some(Exception::Arg0HasBits(libc::CLONE_THREAD as u32)),
+            ..Rule::new(libc::SYS_clone)
+        }];
+        // We can't look inside `clone3`'s args, but saying it doesn't exist
+        // makes libc fall back to `clone`.
+        rules.push(Rule {
+            errno: Some(libc::ENOSYS),
+            ..Rule::new(libc::SYS_clone3)
+        });
+        #[cfg(target_arch = "x86_64")]
+        rules.extend([Rule::new(libc::SYS_fork), Rule::new(libc::SYS_vfork)]);
+        Self {
+            name: "no-exec-children".to_owned(),
+            description: "denies creating child processes".to_owned(),
+            allow_list: false,
+            rules,
+            kill: false,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Kill the command with `SIGSYS` when it makes a denied syscall, rather
+    /// than just failing the syscall with `EPERM`.
+    pub fn set_kill_on_violation(&mut self) -> &mut Self {
+        self.kill = true;
+        self
+    }
+
+    pub fn with_kill_on_violation(mut self) -> Self {
+        self.set_kill_on_violation();
+        self
+    }
+
+    /// Compiles the profile to a BPF program.
+    pub(crate) fn compile(&self) -> io::Result<Filter> {
+        fn stmt(code: u16, k: u32) -> libc::sock_filter {
+            libc::sock_filter {
+                code,
+                jt: 0,
+                jf: 0,
+                k,
+            }
+        }
+
+        fn jump(code: u16, k: u32, jt: u8, jf: u8) -> libc::sock_filter {
+            libc::sock_filter { code, jt, jf, k }
+        }
+
+        let arch = AUDIT_ARCH.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "seccomp profiles aren't supported on this architecture",
+            )
+        })?;
+        let deny = |errno: Option<i32>| match errno {
+            Some(errno) => libc::SECCOMP_RET_ERRNO | (errno as u32 & libc::SECCOMP_RET_DATA),
+            None if self.kill => libc::SECCOMP_RET_KILL_PROCESS,
+            None => libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+        };
+        let (listed, unlisted) = if self.allow_list {
+            (libc::SECCOMP_RET_ALLOW, deny(None))
+        } else {
+            (deny(None), libc::SECCOMP_RET_ALLOW)
+        };
+        let mut program = vec![
+            stmt(BPF_LD_W_ABS, OFFSET_ARCH),
+            jump(BPF_JMP_JEQ_K, arch, 1, 0),
+            stmt(BPF_RET_K, libc::SECCOMP_RET_KILL_PROCESS),
+            stmt(BPF_LD_W_ABS, OFFSET_NR),
+        ];
+        if let Some(bit) = X32_SYSCALL_BIT {
+            program.push(jump(BPF_JMP_JGE_K, bit, 0, 1));
+            program.push(stmt(BPF_RET_K, libc::SECCOMP_RET_KILL_PROCESS));
+        }
+        for rule in &self.rules {
+            let action = match rule.errno {
+                Some(_) => deny(rule.errno),
+                None => listed,
+            };
+            match rule.exception {
+                None => {
+                    program.push(jump(BPF_JMP_JEQ_K, rule.syscall as u32, 0, 1));
+                    program.push(stmt(BPF_RET_K, action));
+                }
+                Some(exception) => {
+                    let check = match exception {
+                        Exception::Arg0Is(value) => jump(BPF_JMP_JEQ_K, value, 0, 1),
+                        Exception::Arg0HasBits(bits) => jump(BPF_JMP_JSET_K, bits, 0, 1),
+                    };
+                    program.extend([
+                        jump(BPF_JMP_JEQ_K, rule.syscall as u32, 0, 4),
+                        stmt(BPF_LD_W_ABS, OFFSET_ARG0),
+                        check,
+                        stmt(BPF_RET_K, libc::SECCOMP_RET_ALLOW),
+                        stmt(BPF_RET_K, action),
+                    ]);
+                }
+            }
+        }
+        program.push(stmt(BPF_RET_K, unlisted));
+        if program.len() > usize::from(libc::BPF_MAXINSNS as u16) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seccomp profile has too many rules",
+            ));
+        }
+        Ok(Filter(program))
+    }
+}
+
+/// A compiled [`SeccompProfile`].
+#[derive(Debug)]
+pub(crate) struct Filter(Vec<libc::sock_filter>);
+
+impl Filter {
+    /// This is meant to run in the child between `fork` and `exec`, so it
+    /// can't allocate.
+    pub fn install(&self) -> io::Result<()> {
+        let program = libc::sock_fprog {
+            len: self.0.len() as libc::c_ushort,
+            filter: self.0.as_ptr() as *mut _,
+        };
+        unsafe {
+            // Without this, only root could install a filter.
+            Step::Seccomp.check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            Step::Seccomp.check(libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                0,
+                &program as *const libc::sock_fprog,
+            ) as libc::c_int)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+//! Serialization for errors and output, so they can be sent to another
+//! process and still render the same way once they get there.
+
+use crate::{redact::Redactor, ExitStatus, Output};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{borrow::Cow, fmt::Display, io, process, str::FromStr, sync::Arc};
+
+/// For anything that can round-trip through a string, like versions.
+pub(crate) mod display {
+    use super::*;
+
+    pub fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+fn kind_name(kind: io::ErrorKind) -> String {
+    format!("{:?}", kind)
+}
+
+fn kind_from_name(name: &str) -> io::ErrorKind {
+    use io::ErrorKind::*;
+    [
+        NotFound,
+        PermissionDenied,
+        ConnectionRefused,
+        ConnectionReset,
+        ConnectionAborted,
+        NotConnected,
+        AddrInUse,
+        AddrNotAvailable,
+        BrokenPipe,
+        AlreadyExists,
+        WouldBlock,
+        InvalidInput,
+        InvalidData,
+        TimedOut,
+        WriteZero,
+        Interrupted,
+        Unsupported,
+        UnexpectedEof,
+        OutOfMemory,
+    ]
+    .iter()
+    .copied()
+    .find(|kind| kind_name(*kind) == name)
+    .unwrap_or(Other)
+}
+
+/// `io::Error`s are sent as their kind, their message, and their OS error
+/// code if they have one.
+pub(crate) mod io_error {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Repr<'a> {
+        kind: String,
+        message: Cow<'a, str>,
+        raw_os_error: Option<i32>,
+    }
+
+    pub fn serialize<S: Serializer>(err: &io::Error, serializer: S) -> Result<S::Ok, S::Error> {
+        Repr {
+            kind: kind_name(err.kind()),
+            message: Cow::Owned(err.to_string()),
+            raw_os_error: err.raw_os_error(),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<io::Error, D::Error> {
+        let repr = Repr::deserialize(deserializer)?;
+        // The OS error is only any use if it says the same thing here as it
+        // did there, which it won't if it came from a different platform.
+        if let Some(code) = repr.raw_os_error {
+            let err = io::Error::from_raw_os_error(code);
+            if err.to_string() == repr.message {
+                return Ok(err);
+            }
+        }
+        Ok(io::Error::new(
+            kind_from_name(&repr.kind),
+            repr.message.into_owned(),
+        ))
+    }
+}
+
+/// Exit statuses are sent as what they mean rather than how the platform
+/// encodes them.
+pub(crate) mod exit_status {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Repr {
+        code: Option<i32>,
+        #[serde(default)]
+        signal: Option<i32>,
+        #[serde(default)]
+        core_dumped: bool,
+    }
+
+    pub fn serialize<S: Serializer>(status: &ExitStatus, serializer: S) -> Result<S::Ok, S::Error> {
+        #[cfg(unix)]
+        let (signal, core_dumped) = {
+            use std::os::unix::process::ExitStatusExt as _;
+            (status.signal(), status.core_dumped())
+        };
+        #[cfg(not(unix))]
+        let (signal, core_dumped) = (None, false);
+        Repr {
+            code: status.code(),
+            signal,
+            core_dumped,
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ExitStatus, D::Error> {
+        let repr = Repr::deserialize(deserializer)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt as _;
+            // This is the encoding `waitpid` uses.
+            let raw = match (repr.code, repr.signal) {
+                (Some(code), _) => (code & 0xff) << 8,
+                (None, Some(signal)) => (signal & 0x7f) | if repr.core_dumped { 0x80 } else { 0 },
+                (None, None) => return Err(de::Error::custom("exit status has no code or signal")),
+            };
+            Ok(ExitStatus::from_raw(raw))
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::ExitStatusExt as _;
+            // There are no signals here, so the closest we can get is the
+            // code the shell would've reported.
+            let code = repr
+                .code
+                .or_else(|| repr.signal.map(|signal| 128 + signal))
+                .unwrap_or(1);
+            Ok(ExitStatus::from_raw(code as u32))
+        }
+    }
+}
+
+/// `Utf8Error` can't be built directly, so we get an identical one by failing
+/// to decode some bytes that go wrong in the same place in the same way.
+pub(crate) mod utf8_error {
+    use super::*;
+    use std::str::Utf8Error;
+
+    #[derive(Serialize, Deserialize)]
+    struct Repr {
+        valid_up_to: usize,
+        error_len: Option<u8>,
+    }
+
+    pub fn serialize<S: Serializer>(err: &Utf8Error, serializer: S) -> Result<S::Ok, S::Error> {
+        Repr {
+            valid_up_to: err.valid_up_to(),
+            error_len: err.error_len().map(|len| len as u8),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Utf8Error, D::Error> {
+        let repr = Repr::deserialize(deserializer)?;
+        let invalid: &[u8] = match repr.error_len {
+            Some(1) => &[0xff],
+            Some(2) => &[0xe1, 0x80, b'a'],
+            Some(3) => &[0xf1, 0x80, 0x80, b'a'],
+            None => &[0xe1],
+            Some(len) => {
+                return Err(de::Error::custom(format!(
+                    "invalid UTF-8 sequences are at most 3 bytes long, not {}",
+                    len
+                )))
+            }
+        };
+        let mut bytes = vec![b'a'; repr.valid_up_to];
+        bytes.extend_from_slice(invalid);
+        Ok(std::str::from_utf8(&bytes).expect_err("developer error: invalid UTF-8 was valid"))
+    }
+}
+
+/// Output is sent as text when it's valid UTF-8, since that's much nicer to
+/// read (and smaller) than a list of bytes.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Stream<'a> {
+    Text(Cow<'a, str>),
+    Bytes(Cow<'a, [u8]>),
+}
+
+impl<'a> Stream<'a> {
+    fn new(output: &Output, bytes: &'a [u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Self::Text(output.redact(text)),
+            // Invalid UTF-8 only gets displayed lossily anyway, so if there
+            // were secrets in there, we'd rather lose the invalid bytes than
+            // leak the secrets.
+            Err(_) => match output.redact(&String::from_utf8_lossy(bytes)) {
+                Cow::Owned(redacted) => Self::Text(Cow::Owned(redacted)),
+                Cow::Borrowed(_) => Self::Bytes(Cow::Borrowed(bytes)),
+            },
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Text(text) => text.into_owned().into_bytes(),
+            Self::Bytes(bytes) => bytes.into_owned(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct OutputRepr<'a> {
+    command: Cow<'a, str>,
+    #[serde(with = "exit_status")]
+    status: ExitStatus,
+    stdout: Stream<'a>,
+    stderr: Stream<'a>,
+}
+
+/// Secrets are redacted before output is serialized, so they never leave the
+/// process.
+impl Serialize for Output {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        OutputRepr {
+            command: Cow::Borrowed(self.command()),
+            status: self.status(),
+            stdout: Stream::new(self, self.stdout()),
+            stderr: Stream::new(self, self.stderr()),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Output {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = OutputRepr::deserialize(deserializer)?;
+        Ok(Output::new(
+            repr.command.into_owned(),
+            Arc::new(Redactor::default()),
+            process::Output {
+                status: repr.status,
+                stdout: repr.stdout.into_bytes(),
+                stderr: repr.stderr.into_bytes(),
+            },
+        ))
+    }
+}
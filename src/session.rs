@@ -0,0 +1,239 @@
+use crate::{handle::Reader, Cause, Error, ExitStatus, Handle, Regex};
+use std::{
+    io, str,
+    time::{Duration, Instant},
+};
+
+/// What an [`Session::expect_regex`] call matched.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    before: String,
+    groups: Vec<Option<String>>,
+}
+
+impl Match {
+    /// Everything the command output between the previous match and this one.
+    pub fn before(&self) -> &str {
+        &self.before
+    }
+
+    /// The text the whole pattern matched.
+    pub fn matched(&self) -> &str {
+        self.group(0)
+            .expect("developer error: match is missing group 0")
+    }
+
+    /// The text a capture group matched, if it participated in the match.
+    pub fn group(&self, index: usize) -> Option<&str> {
+        self.groups.get(index)?.as_deref()
+    }
+}
+
+/// Drives an interactive command, in the spirit of `expect`. Everything the
+/// command outputs (stdout and stderr together) lands in a buffer, which the
+/// `expect` methods consume up through whatever they're waiting for.
+///
+/// This works over plain pipes, but a lot of programs only prompt when they're
+/// talking to a terminal, so on Linux you might want
+/// [`Command::set_pty`](crate::Command::set_pty) too.
+#[derive(Debug)]
+#[must_use = "sessions must be `wait`ed on, or they won't stop"]
+pub struct Session {
+    handle: Handle,
+    // This is `None` once the command closes its output.
+    reader: Option<Reader>,
+    buffer: String,
+    // The tail end of a UTF-8 character that got split across chunks.
+    partial: Vec<u8>,
+    timeout: Duration,
+}
+
+impl Session {
+    /// For best results, the command should have been run with stdin, stdout,
+    /// and stderr piped (or attached to a pseudo-terminal), which
+    /// [`Command::run_interactive`](crate::Command::run_interactive) takes
+    /// care of for you. By default, each `expect` waits up to 30 seconds.
+    pub fn new(mut handle: Handle) -> Self {
+        let reader = handle.take_reader();
+        Self {
+            handle,
+            reader,
+            buffer: String::new(),
+            partial: Vec::new(),
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+        self
+    }
+
+    /// Whatever the command has output that hasn't been consumed yet.
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    /// The handle to the command, for when you need to do something this
+    /// doesn't cover, like resizing its terminal.
+    pub fn handle(&mut self) -> &mut Handle {
+        &mut self.handle
+    }
+
+    fn error(&self, cause: Cause) -> Error {
+        Error::new(self.handle.command().to_owned(), cause)
+    }
+
+    fn redacted_buffer(&self) -> String {
+        self.handle.redactor().redact(&self.buffer).into_owned()
+    }
+
+    fn push(&mut self, chunk: Vec<u8>) {
+        let mut bytes = std::mem::take(&mut self.partial);
+        bytes.extend(chunk);
+        let mut rest = bytes.as_slice();
+        loop {
+            match str::from_utf8(rest) {
+                Ok(valid) => {
+                    self.buffer.push_str(valid);
+                    break;
+                }
+                Err(err) => {
+                    let (valid, invalid) = rest.split_at(err.valid_up_to());
+                    self.buffer
+                        .push_str(str::from_utf8(valid).expect("developer error: invalid UTF-8"));
+                    match err.error_len() {
+                        Some(len) => {
+                            self.buffer.push(char::REPLACEMENT_CHARACTER);
+                            rest = &invalid[len..];
+                        }
+                        // The rest of this character is in the next chunk.
+                        None => {
+                            self.partial = invalid.to_vec();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads until `find` finds something in the buffer, returning where the
+    /// match ends (so we know how much to consume) along with what it found.
+    /// `find` is also told whether or not the command's closed its output.
+    fn expect_with<T>(
+        &mut self,
+        expected: String,
+        find: impl Fn(&str, bool) -> Option<(usize, T)>,
+    ) -> crate::Result<T> {
+        log::debug!(
+            "expecting {} from command {:?}",
+            expected,
+            self.handle.command()
+        );
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some((end, found)) = find(&self.buffer, self.reader.is_none()) {
+                self.buffer.drain(..end);
+                return Ok(found);
+            }
+            let reader = match self.reader.as_ref() {
+                Some(reader) => reader,
+                None => {
+                    let buffer = self.redacted_buffer();
+                    return Err(self.error(Cause::ExpectEof { expected, buffer }));
+                }
+            };
+            let now = Instant::now();
+            if now >= deadline {
+                let buffer = self.redacted_buffer();
+                return Err(self.error(Cause::ExpectTimedOut {
+                    expected,
+                    timeout: self.timeout,
+                    buffer,
+                }));
+            }
+            match reader.next_timeout(deadline - now) {
+                Some(Ok(chunk)) => self.push(chunk),
+                Some(Err(err)) => return Err(self.error(Cause::SessionIoFailed(err))),
+                None => {
+                    self.reader = None;
+                    let partial = std::mem::take(&mut self.partial);
+                    self.buffer.push_str(&String::from_utf8_lossy(&partial));
+                }
+            }
+        }
+    }
+
+    /// Wait for the command to output `text`, returning everything it output
+    /// before that.
+    pub fn expect(&mut self, text: &str) -> crate::Result<String> {
+        self.expect_with(format!("{:?}", text), |buffer, _| {
+            buffer
+                .find(text)
+                .map(|start| (start + text.len(), buffer[..start].to_owned()))
+        })
+    }
+
+    /// Wait for the command to output something matching `regex`.
+    pub fn expect_regex(&mut self, regex: &Regex) -> crate::Result<Match> {
+        self.expect_with(format!("pattern {:?}", regex.as_str()), |buffer, _| {
+            regex.captures(buffer).map(|captures| {
+                let whole = captures
+                    .get(0)
+                    .expect("developer error: captures are missing group 0");
+                let groups = captures
+                    .iter()
+                    .map(|group| group.map(|group| group.as_str().to_owned()))
+                    .collect();
+                let before = buffer[..whole.start()].to_owned();
+                (whole.end(), Match { before, groups })
+            })
+        })
+    }
+
+    /// Wait for the command to close its output (which usually means it
+    /// exited), returning everything it output that hasn't been consumed yet.
+    pub fn expect_eof(&mut self) -> crate::Result<String> {
+        self.expect_with("EOF".to_owned(), |buffer, eof| {
+            Some((buffer.len(), buffer.to_owned())).filter(|_| eof)
+        })
+    }
+
+    /// Write `text` to the command's stdin.
+    pub fn send(&mut self, text: &str) -> crate::Result<()> {
+        log::debug!(
+            "sending {:?} to command {:?}",
+            self.handle.redactor().redact(text),
+            self.handle.command()
+        );
+        let result = match self.handle.input() {
+            Some(input) => input.write_all(text.as_bytes()).and_then(|_| input.flush()),
+            None => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "stdin isn't piped",
+            )),
+        };
+        result.map_err(|err| self.error(Cause::SessionIoFailed(err)))
+    }
+
+    /// Write `line` to the command's stdin, followed by a newline.
+    pub fn send_line(&mut self, line: &str) -> crate::Result<()> {
+        self.send(&format!("{}\n", line))
+    }
+
+    /// Wait for the command to exit. Anything it outputs in the meantime is
+    /// discarded.
+    pub fn wait(self) -> crate::Result<ExitStatus> {
+        let Self { handle, reader, .. } = self;
+        let result = handle.wait();
+        // Keep reading until the command's done, so it doesn't get `SIGPIPE`.
+        drop(reader);
+        result
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    io, mem,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+const MAX_SIGNAL: usize = 64;
+
+// How many times each signal's been caught, split by whether it was sent by
+// a process or raised by the kernel (which includes the terminal). Every
+// catcher keeps track of how many it's seen, so they each see every signal.
+static SENT: [AtomicUsize; MAX_SIGNAL + 1] = [const { AtomicUsize::new(0) }; MAX_SIGNAL + 1];
+static RAISED: [AtomicUsize; MAX_SIGNAL + 1] = [const { AtomicUsize::new(0) }; MAX_SIGNAL + 1];
+
+// The signals we're catching, with how many catchers want each of them and
+// the handler to restore once none do.
+static INSTALLED: Mutex<Vec<(libc::c_int, usize, libc::sigaction)>> = Mutex::new(Vec::new());
+
+extern "C" fn catch(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    // Kernel-generated signals have a positive `si_code`.
+    let counts = if !info.is_null() && unsafe { (*info).si_code } > 0 {
+        &RAISED
+    } else {
+        &SENT
+    };
+    if let Some(count) = counts.get(signal as usize) {
+        count.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// A signal that a [`Catcher`] caught.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Caught {
+    pub signal: libc::c_int,
+    /// Whether the kernel raised the signal, rather than some process sending
+    /// it. This is how signals from the terminal arrive.
+    pub raised: bool,
+}
+
+/// Catches signals until it's dropped, rather than letting them do whatever
+/// they'd usually do (which is usually killing us).
+#[derive(Debug)]
+pub(crate) struct Catcher {
+    // Each signal, with how many times it's been sent and raised as of the
+    // last time we checked.
+    seen: Vec<(libc::c_int, usize, usize)>,
+}
+
+impl Catcher {
+    /// If a signal can't be caught, then the error comes with that signal.
+    pub fn new(signals: &[libc::c_int]) -> Result<Self, (libc::c_int, io::Error)> {
+        // If we fail partway through, then this is dropped after `installed`
+        // is unlocked, which undoes everything we managed.
+        let mut this = Self {
+            seen: Vec::with_capacity(signals.len()),
+        };
+        let mut installed = INSTALLED.lock().unwrap_or_else(PoisonError::into_inner);
+        for &signal in signals {
+            if signal < 1 || signal as usize > MAX_SIGNAL {
+                return Err((signal, io::Error::from_raw_os_error(libc::EINVAL)));
+            }
+            if this.seen.iter().any(|(seen, ..)| *seen == signal) {
+                continue;
+            }
+            match installed
+                .iter_mut()
+                .find(|(installed, ..)| *installed == signal)
+            {
+                Some((_, users, _)) => *users += 1,
+                None => unsafe {
+                    let mut action = mem::zeroed::<libc::sigaction>();
+                    action.sa_sigaction = catch
+                        as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)
+                        as libc::sighandler_t;
+                    action.sa_flags = libc::SA_RESTART | libc::SA_SIGINFO;
+                    libc::sigemptyset(&mut action.sa_mask);
+                    let mut previous = mem::zeroed::<libc::sigaction>();
+                    if libc::sigaction(signal, &action, &mut previous) == -1 {
+                        return Err((signal, io::Error::last_os_error()));
+                    }
+                    installed.push((signal, 1, previous));
+                },
+            }
+            let index = signal as usize;
+            this.seen.push((
+                signal,
+                SENT[index].load(Ordering::SeqCst),
+                RAISED[index].load(Ordering::SeqCst),
+            ));
+        }
+        Ok(this)
+    }
+
+    /// Takes a signal that's been caught since the last time this was
+    /// called, if there is one.
+    pub fn take(&mut self) -> Option<Caught> {
+        fn check(count: &AtomicUsize, seen: &mut usize) -> bool {
+            let caught = count.load(Ordering::SeqCst) != *seen;
+            if caught {
+                *seen = seen.wrapping_add(1);
+            }
+            caught
+        }
+
+        self.seen.iter_mut().find_map(|(signal, sent, raised)| {
+            let index = *signal as usize;
+            if check(&SENT[index], sent) {
+                Some(Caught {
+                    signal: *signal,
+                    raised: false,
+                })
+            } else if check(&RAISED[index], raised) {
+                Some(Caught {
+                    signal: *signal,
+                    raised: true,
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Forwards every signal caught since the last time this was called to
+    /// `pid`, or to its whole process group if it leads one. Signals from the
+    /// terminal aren't forwarded to a child in our own process group, since
+    /// the terminal sends them to the whole group anyway.
+    pub fn forward(&mut self, pid: u32) {
+        let pid = pid as libc::pid_t;
+        while let Some(caught) = self.take() {
+            let group = unsafe { libc::getpgid(pid) };
+            let target = if group == pid {
+                -pid
+            } else if caught.raised && group == unsafe { libc::getpgrp() } {
+                log::debug!(
+                    "not forwarding signal {} to pid {}, since it shares our process group",
+                    caught.signal,
+                    pid
+                );
+                continue;
+            } else {
+                pid
+            };
+            log::info!("forwarding signal {} to pid {}", caught.signal, target);
+            if unsafe { libc::kill(target, caught.signal) } == -1 {
+                log::warn!(
+                    "failed to forward signal {} to pid {}: {}",
+                    caught.signal,
+                    target,
+                    io::Error::last_os_error()
+                );
+            }
+        }
+    }
+}
+
+impl Drop for Catcher {
+    fn drop(&mut self) {
+        let mut installed = INSTALLED.lock().unwrap_or_else(PoisonError::into_inner);
+        for (signal, ..) in self.seen.drain(..) {
+            if let Some(index) = installed
+                .iter()
+                .position(|(installed, ..)| *installed == signal)
+            {
+                installed[index].1 -= 1;
+                if installed[index].1 == 0 {
+                    let (_, _, previous) = installed.remove(index);
+                    unsafe {
+                        libc::sigaction(signal, &previous, std::ptr::null_mut());
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,459 @@
+use crate::{dry_run, handle::Watch, CancellationToken, Command, Error};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Display},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// When a [`Supervisor`] restarts a command after it exits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Restart {
+    Always,
+    /// Only restart the command if it fails, which includes failing to start.
+    OnFailure,
+    Never,
+}
+
+/// How a [`Supervisor`] restarts a command. Restarts are delayed by a
+/// backoff that doubles with every restart, starting over once the command
+/// manages to stay up for as long as the longest backoff. If the command
+/// needs restarting more than `max_restarts` times within `window`, then the
+/// supervisor gives up on it.
+#[derive(Clone, Debug)]
+pub struct RestartPolicy {
+    restart: Restart,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    max_restarts: u32,
+    window: Duration,
+}
+
+impl From<Restart> for RestartPolicy {
+    fn from(restart: Restart) -> Self {
+        Self::new(restart)
+    }
+}
+
+impl RestartPolicy {
+    /// By default, the backoff goes from 100ms to 30s, and the supervisor
+    /// gives up after 5 restarts within a minute.
+    pub fn new(restart: Restart) -> Self {
+        Self {
+            restart,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(30),
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+        }
+    }
+
+    pub fn set_backoff(&mut self, initial: Duration, max: Duration) -> &mut Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.set_backoff(initial, max);
+        self
+    }
+
+    pub fn set_max_restarts(&mut self, max_restarts: u32, window: Duration) -> &mut Self {
+        self.max_restarts = max_restarts;
+        self.window = window;
+        self
+    }
+
+    pub fn with_max_restarts(mut self, max_restarts: u32, window: Duration) -> Self {
+        self.set_max_restarts(max_restarts, window);
+        self
+    }
+
+    fn should_restart(&self, failed: bool) -> bool {
+        match self.restart {
+            Restart::Always => true,
+            Restart::OnFailure => failed,
+            Restart::Never => false,
+        }
+    }
+}
+
+/// What a supervised command is up to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ServiceState {
+    Starting,
+    Running,
+    /// Waiting out the backoff before restarting.
+    Restarting,
+    /// Exited, and the restart policy says to leave it that way.
+    Exited,
+    /// Restarted too many times, so the supervisor gave up on it.
+    GaveUp,
+    /// Stopped by the supervisor shutting down.
+    Stopped,
+}
+
+impl Display for ServiceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Starting => "starting",
+            Self::Running => "running",
+            Self::Restarting => "restarting",
+            Self::Exited => "exited",
+            Self::GaveUp => "gave up",
+            Self::Stopped => "stopped",
+        })
+    }
+}
+
+/// A snapshot of how a supervised command is doing.
+#[derive(Clone, Debug)]
+pub struct ServiceStatus {
+    name: String,
+    state: ServiceState,
+    pid: Option<u32>,
+    started: Option<Instant>,
+    restarts: u32,
+    last_error: Option<Arc<Error>>,
+}
+
+impl Display for ServiceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}", self.name, self.state)?;
+        if let (Some(pid), Some(uptime)) = (self.pid, self.uptime()) {
+            write!(f, ", pid {}, up {:.0?}", pid, uptime)?;
+        }
+        write!(f, ", {} restarts)", self.restarts)?;
+        if let Some(err) = &self.last_error {
+            write!(f, ": {}", err)?;
+        }
+        Ok(())
+    }
+}
+
+impl ServiceStatus {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            state: ServiceState::Starting,
+            pid: None,
+            started: None,
+            restarts: 0,
+            last_error: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> ServiceState {
+        self.state
+    }
+
+    /// This is `None` whenever the command isn't running.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// How long the command's been running since it was last (re)started.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.started.map(|started| started.elapsed())
+    }
+
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+
+    /// Why the command last failed, if it ever has.
+    pub fn last_error(&self) -> Option<&Error> {
+        self.last_error.as_deref()
+    }
+}
+
+struct Service {
+    status: Arc<Mutex<ServiceStatus>>,
+    thread: Option<thread::JoinHandle<()>>,
+    // This is taken once the service starts.
+    pending: Option<(Command, RestartPolicy)>,
+}
+
+fn lock(status: &Mutex<ServiceStatus>) -> MutexGuard<'_, ServiceStatus> {
+    status.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Sleeps for `duration`, unless `stop` is cancelled first. Returns whether
+/// or not it was.
+fn sleep_unless(stop: &CancellationToken, duration: Duration) -> bool {
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    let deadline = Instant::now() + duration;
+    loop {
+        if stop.is_cancelled() {
+            return true;
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+fn supervise(
+    mut command: Command,
+    policy: RestartPolicy,
+    status: Arc<Mutex<ServiceStatus>>,
+    stop: CancellationToken,
+) {
+    let name = lock(&status).name.clone();
+    let mut restarts = VecDeque::new();
+    let mut backoff = policy.initial_backoff;
+    let final_state = loop {
+        let started = Instant::now();
+        let result = match command.run() {
+            Ok(mut handle) => {
+                {
+                    let mut status = lock(&status);
+                    status.state = ServiceState::Running;
+                    status.pid = handle.pid();
+                    status.started = Some(started);
+                }
+                let mut watch = Watch::default();
+                watch.cancel_on(&stop);
+                let result = handle.wait_watched(watch);
+                if stop.is_cancelled() {
+                    break ServiceState::Stopped;
+                }
+                result.map(drop)
+            }
+            Err(err) => Err(err),
+        };
+        let failed = result.is_err();
+        {
+            let mut status = lock(&status);
+            status.pid = None;
+            status.started = None;
+            if let Err(err) = result {
+                log::warn!("supervised command {:?} failed: {}", name, err);
+                status.last_error = Some(Arc::new(err));
+            }
+        }
+        if !policy.should_restart(failed) {
+            log::info!("supervised command {:?} exited", name);
+            break ServiceState::Exited;
+        }
+        let now = Instant::now();
+        while restarts
+            .front()
+            .is_some_and(|restart| now.duration_since(*restart) > policy.window)
+        {
+            restarts.pop_front();
+        }
+        if restarts.len() >= policy.max_restarts as usize {
+            log::error!(
+                "supervised command {:?} was restarted {} times within {:?}, so giving up on it",
+                name,
+                restarts.len(),
+                policy.window
+            );
+            break ServiceState::GaveUp;
+        }
+        if now.duration_since(started) >= policy.max_backoff {
+            backoff = policy.initial_backoff;
+        }
+        log::info!("restarting supervised command {:?} in {:?}", name, backoff);
+        lock(&status).state = ServiceState::Restarting;
+        if sleep_unless(&stop, backoff) {
+            break ServiceState::Stopped;
+        }
+        backoff = (backoff * 2).min(policy.max_backoff);
+        restarts.push_back(Instant::now());
+        lock(&status).restarts += 1;
+    };
+    let mut status = lock(&status);
+    status.state = final_state;
+    status.pid = None;
+    status.started = None;
+}
+
+/// Keeps a set of long-running commands alive, restarting them according to
+/// their [`RestartPolicy`]. Each command is watched from its own thread.
+///
+/// Everything gets shut down when the supervisor's dropped: commands are
+/// asked to exit (with `SIGTERM` on Unix), and killed if they're still
+/// running after the grace period.
+pub struct Supervisor {
+    services: Vec<Service>,
+    // This carries the grace period too.
+    stop: CancellationToken,
+    started: bool,
+}
+
+impl fmt::Debug for Supervisor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("status", &self.status())
+            .field("grace", &self.stop.grace_period())
+            .finish()
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Supervisor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl Supervisor {
+    /// The grace period defaults to 5s.
+    pub fn new() -> Self {
+        Self {
+            services: Vec::new(),
+            stop: CancellationToken::new().with_grace_period(Duration::from_secs(5)),
+            started: false,
+        }
+    }
+
+    /// How long commands get to exit on their own when shutting down.
+    pub fn set_grace_period(&mut self, grace: Duration) -> &mut Self {
+        self.stop.set_grace_period(grace);
+        self
+    }
+
+    pub fn with_grace_period(mut self, grace: Duration) -> Self {
+        self.set_grace_period(grace);
+        self
+    }
+
+    /// Supervise `command` under `name`. If the supervisor's already been
+    /// started, then so is the command.
+    pub fn add_command(
+        &mut self,
+        name: impl Into<String>,
+        command: Command,
+        policy: impl Into<RestartPolicy>,
+    ) -> &mut Self {
+        let name = name.into();
+        log::debug!(
+            "adding command {:?} to supervisor as {:?}",
+            command.display(),
+            name
+        );
+        self.services.push(Service {
+            status: Arc::new(Mutex::new(ServiceStatus::new(name))),
+            thread: None,
+            pending: Some((command, policy.into())),
+        });
+        if self.started {
+            self.start();
+        }
+        self
+    }
+
+    pub fn with_command(
+        mut self,
+        name: impl Into<String>,
+        command: Command,
+        policy: impl Into<RestartPolicy>,
+    ) -> Self {
+        self.add_command(name, command, policy);
+        self
+    }
+
+    /// Start supervising every command that isn't already. This doesn't
+    /// block.
+    pub fn start(&mut self) -> &mut Self {
+        self.started = true;
+        // Shutting down leaves the token cancelled, so starting again needs
+        // a fresh one.
+        if self.stop.is_cancelled() {
+            let mut stop = CancellationToken::new();
+            if let Some(grace) = self.stop.grace_period() {
+                stop.set_grace_period(grace);
+            }
+            self.stop = stop;
+        }
+        // Dry-run scopes are per-thread, so each service needs its own.
+        let is_dry_run = dry_run::is_dry_run();
+        for service in &mut self.services {
+            if let Some((command, policy)) = service.pending.take() {
+                let status = Arc::clone(&service.status);
+                let stop = self.stop.clone();
+                service.thread = Some(thread::spawn(move || {
+                    let _dry_run = dry_run::scoped_dry_run(is_dry_run);
+                    supervise(command, policy, status, stop)
+                }));
+            }
+        }
+        self
+    }
+
+    /// A snapshot of how every command is doing, in the order they were
+    /// added.
+    pub fn status(&self) -> Vec<ServiceStatus> {
+        self.services
+            .iter()
+            .map(|service| lock(&service.status).clone())
+            .collect()
+    }
+
+    /// Whether or not any command is still running, or going to be.
+    pub fn is_active(&self) -> bool {
+        self.services.iter().any(|service| {
+            service.pending.is_some()
+                || matches!(
+                    lock(&service.status).state,
+                    ServiceState::Starting | ServiceState::Running | ServiceState::Restarting
+                )
+        })
+    }
+
+    /// Stop every command, waiting for them all to exit. This happens
+    /// automatically when the supervisor's dropped. The supervisor can be
+    /// started again afterwards, though only commands added since will run.
+    pub fn shutdown(&mut self) {
+        self.started = false;
+        if self.services.iter().all(|service| service.thread.is_none()) {
+            return;
+        }
+        log::info!("shutting down supervisor");
+        self.stop.cancel();
+        for service in &mut self.services {
+            if let Some(thread) = service.thread.take() {
+                if thread.join().is_err() {
+                    log::error!(
+                        "thread supervising {:?} panicked",
+                        lock(&service.status).name
+                    );
+                }
+            }
+        }
+    }
+
+    /// Start supervising, and block until we get `SIGINT` or `SIGTERM` (or
+    /// every command's done for good), then shut down.
+    #[cfg(unix)]
+    pub fn run_until_signal(&mut self) -> std::io::Result<()> {
+        let mut catcher =
+            crate::signal::Catcher::new(&[libc::SIGINT, libc::SIGTERM]).map_err(|(_, err)| err)?;
+        self.start();
+        while self.is_active() {
+            if let Some(caught) = catcher.take() {
+                log::info!("supervisor got signal {}", caught.signal);
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        self.shutdown();
+        Ok(())
+    }
+}
@@ -0,0 +1,469 @@
+use crate::{dry_run, Cause, Command, Error, Output};
+use std::{
+    fmt::{self, Display},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Identifies a task within the [`TaskGraph`] that created it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId {
+    graph: u64,
+    index: usize,
+}
+
+// Every graph gets its own number, so their `TaskId`s can't be mixed up.
+static NEXT_GRAPH: AtomicU64 = AtomicU64::new(0);
+
+enum Action {
+    Command(Box<Command>),
+    Deferred(Box<dyn FnOnce() -> Command + Send>),
+}
+
+impl Action {
+    fn describe(&self) -> &str {
+        match self {
+            Self::Command(command) => command.display(),
+            Self::Deferred(_) => "(deferred)",
+        }
+    }
+
+    fn into_command(self) -> Command {
+        match self {
+            Self::Command(command) => *command,
+            Self::Deferred(f) => f(),
+        }
+    }
+}
+
+struct Task {
+    name: String,
+    deps: Vec<TaskId>,
+    action: Action,
+}
+
+/// The error returned when a [`TaskGraph`] has a dependency cycle, which
+/// would otherwise make it impossible to run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleError {
+    cycle: Vec<String>,
+}
+
+impl Display for CycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Task graph has a cycle: {}", self.cycle.join(" -> "))
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+impl CycleError {
+    /// The names of the tasks in the cycle, with the first task repeated at the
+    /// end.
+    pub fn cycle(&self) -> &[String] {
+        &self.cycle
+    }
+}
+
+/// Quotes `s` as a DOT ID. Only quotes and backslashes need escaping; unlike
+/// Rust's escapes, anything else can go in as it is.
+fn dot_id(s: &str) -> String {
+    let mut id = String::with_capacity(s.len() + 2);
+    id.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            id.push('\\');
+        }
+        id.push(c);
+    }
+    id.push('"');
+    id
+}
+
+/// A bunch of commands that depend on each other. Running the graph runs
+/// every task as soon as its dependencies have succeeded, with as much
+/// parallelism as is allowed. If a task fails, then everything depending on
+/// it is skipped.
+pub struct TaskGraph {
+    id: u64,
+    tasks: Vec<Task>,
+    max_parallel: usize,
+}
+
+impl Default for TaskGraph {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for TaskGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskGraph")
+            .field("tasks", &self.to_string())
+            .field("max_parallel", &self.max_parallel)
+            .finish()
+    }
+}
+
+/// Lists each task, its command, and what it depends on.
+impl Display for TaskGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, task) in self.tasks.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", task.name, task.action.describe())?;
+            if !task.deps.is_empty() {
+                let deps = task
+                    .deps
+                    .iter()
+                    .map(|dep| self.tasks[dep.index].name.as_str())
+                    .collect::<Vec<_>>();
+                write!(f, " (after {})", deps.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TaskGraph {
+    /// By default, as many tasks run at once as there are CPUs.
+    pub fn new() -> Self {
+        Self {
+            id: NEXT_GRAPH.fetch_add(1, Ordering::Relaxed),
+            tasks: Vec::new(),
+            max_parallel: thread::available_parallelism().map(Into::into).unwrap_or(1),
+        }
+    }
+
+    /// A `max_parallel` of `0` is treated as `1`.
+    pub fn set_max_parallel(&mut self, max_parallel: usize) -> &mut Self {
+        self.max_parallel = max_parallel.max(1);
+        self
+    }
+
+    pub fn with_max_parallel(mut self, max_parallel: usize) -> Self {
+        self.set_max_parallel(max_parallel);
+        self
+    }
+
+    fn push(&mut self, name: String, action: Action, deps: &[TaskId]) -> TaskId {
+        let id = TaskId {
+            graph: self.id,
+            index: self.tasks.len(),
+        };
+        self.tasks.push(Task {
+            name,
+            deps: Vec::new(),
+            action,
+        });
+        for dep in deps {
+            self.add_dependency(id, *dep);
+        }
+        id
+    }
+
+    pub fn add_task(
+        &mut self,
+        name: impl Into<String>,
+        command: Command,
+        deps: &[TaskId],
+    ) -> TaskId {
+        self.push(name.into(), Action::Command(Box::new(command)), deps)
+    }
+
+    /// The same as `add_task`, but the command isn't built until all of its
+    /// dependencies have succeeded. This is useful when the command depends
+    /// on what earlier tasks produced.
+    pub fn add_deferred_task(
+        &mut self,
+        name: impl Into<String>,
+        f: impl FnOnce() -> Command + Send + 'static,
+        deps: &[TaskId],
+    ) -> TaskId {
+        self.push(name.into(), Action::Deferred(Box::new(f)), deps)
+    }
+
+    /// Make `task` wait for `dep` to succeed before running. Panics if either
+    /// task came from a different graph.
+    pub fn add_dependency(&mut self, task: TaskId, dep: TaskId) -> &mut Self {
+        assert!(
+            task.graph == self.id && dep.graph == self.id,
+            "developer error: `TaskId` from a different `TaskGraph`"
+        );
+        if !self.tasks[task.index].deps.contains(&dep) {
+            self.tasks[task.index].deps.push(dep);
+        }
+        self
+    }
+
+    /// Make sure the graph can actually be run.
+    pub fn check(&self) -> Result<(), CycleError> {
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        enum Mark {
+            Unvisited,
+            InProgress,
+            Done,
+        }
+
+        fn visit(
+            graph: &TaskGraph,
+            marks: &mut [Mark],
+            path: &mut Vec<usize>,
+            index: usize,
+        ) -> Result<(), CycleError> {
+            match marks[index] {
+                Mark::Done => return Ok(()),
+                Mark::InProgress => {
+                    let start = path
+                        .iter()
+                        .position(|i| *i == index)
+                        .expect("developer error: in-progress task missing from path");
+                    let cycle = path[start..]
+                        .iter()
+                        .chain(std::iter::once(&index))
+                        .map(|i| graph.tasks[*i].name.clone())
+                        .collect();
+                    return Err(CycleError { cycle });
+                }
+                Mark::Unvisited => (),
+            }
+            marks[index] = Mark::InProgress;
+            path.push(index);
+            for dep in &graph.tasks[index].deps {
+                visit(graph, marks, path, dep.index)?;
+            }
+            path.pop();
+            marks[index] = Mark::Done;
+            Ok(())
+        }
+
+        let mut marks = vec![Mark::Unvisited; self.tasks.len()];
+        let mut path = Vec::new();
+        (0..self.tasks.len()).try_for_each(|index| visit(self, &mut marks, &mut path, index))
+    }
+
+    /// Render the graph in Graphviz's DOT format, with edges pointing from
+    /// each dependency to the tasks that depend on it.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph {\n");
+        for task in &self.tasks {
+            dot.push_str(&format!(
+                "    {} [tooltip={}];\n",
+                dot_id(&task.name),
+                dot_id(task.action.describe())
+            ));
+        }
+        for task in &self.tasks {
+            for dep in &task.deps {
+                dot.push_str(&format!(
+                    "    {} -> {};\n",
+                    dot_id(&self.tasks[dep.index].name),
+                    dot_id(&task.name)
+                ));
+            }
+        }
+        dot.push('}');
+        dot
+    }
+
+    /// Run every task, blocking until they've all either finished or been
+    /// skipped.
+    pub fn run(self) -> Result<TaskReport, CycleError> {
+        self.check()?;
+        let Self {
+            id,
+            tasks,
+            max_parallel,
+        } = self;
+        log::info!(
+            "running task graph with {} tasks, up to {} at a time",
+            tasks.len(),
+            max_parallel
+        );
+        let start = Instant::now();
+        let deps = tasks
+            .iter()
+            .map(|task| task.deps.clone())
+            .collect::<Vec<_>>();
+        let mut pending = tasks
+            .into_iter()
+            .map(|task| Some((task.name, task.action)))
+            .collect::<Vec<_>>();
+        let mut results = pending.iter().map(|_| None).collect::<Vec<_>>();
+        let (tx, rx) = mpsc::channel();
+        let mut running = 0;
+        // Dry-run scopes are per-thread, so each task needs its own.
+        let is_dry_run = dry_run::is_dry_run();
+        thread::scope(|scope| loop {
+            for index in 0..pending.len() {
+                if pending[index].is_none() {
+                    continue;
+                }
+                let dep_results = deps[index]
+                    .iter()
+                    .map(|dep| results[dep.index].as_ref().map(TaskResult::succeeded));
+                if dep_results
+                    .clone()
+                    .any(|succeeded| succeeded == Some(false))
+                {
+                    let (name, action) = pending[index].take().unwrap();
+                    log::info!("skipping task {:?}, since a dependency failed", name);
+                    let command = action.describe().to_owned();
+                    results[index] = Some(TaskResult {
+                        name,
+                        result: Err(Error::new(command, Cause::Skipped)),
+                        started: None,
+                        duration: Duration::default(),
+                    });
+                } else if running < max_parallel && dep_results.clone().all(|s| s == Some(true)) {
+                    let (name, action) = pending[index].take().unwrap();
+                    let tx = tx.clone();
+                    running += 1;
+                    scope.spawn(move || {
+                        // If this panics without sending anything, then we'd
+                        // wait for it forever, so the panic gets sent instead.
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                            let _dry_run = dry_run::scoped_dry_run(is_dry_run);
+                            log::info!("starting task {:?}", name);
+                            let started = start.elapsed();
+                            let result = action.into_command().run_and_wait_for_output();
+                            let duration = start.elapsed() - started;
+                            TaskResult {
+                                name,
+                                result,
+                                started: Some(started),
+                                duration,
+                            }
+                        }));
+                        tx.send((index, result))
+                            .expect("developer error: task graph receiver dropped");
+                    });
+                }
+            }
+            if running == 0 {
+                if pending.iter().all(Option::is_none) {
+                    break;
+                }
+                // Skipping a task can make its dependents skippable too, so
+                // we just need another pass. Since there are no cycles, this
+                // always makes progress.
+                continue;
+            }
+            let (index, result) = rx
+                .recv()
+                .expect("developer error: task graph senders dropped");
+            running -= 1;
+            match result {
+                Ok(result) => results[index] = Some(result),
+                // The scope waits for the other tasks to finish before this
+                // gets any further.
+                Err(payload) => panic::resume_unwind(payload),
+            }
+        });
+        Ok(TaskReport {
+            graph: id,
+            results: results
+                .into_iter()
+                .map(|result| result.expect("developer error: task never finished"))
+                .collect(),
+            duration: start.elapsed(),
+        })
+    }
+}
+
+/// How a single task in a [`TaskGraph`] went.
+#[derive(Debug)]
+pub struct TaskResult {
+    name: String,
+    result: crate::Result<Output>,
+    started: Option<Duration>,
+    duration: Duration,
+}
+
+impl TaskResult {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn result(&self) -> &crate::Result<Output> {
+        &self.result
+    }
+
+    pub fn into_result(self) -> crate::Result<Output> {
+        self.result
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.result.is_ok()
+    }
+
+    /// How long after the graph started running this task started, or `None`
+    /// if it was skipped.
+    pub fn started(&self) -> Option<Duration> {
+        self.started
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
+
+/// How every task in a [`TaskGraph`] went, in the order they were added.
+#[derive(Debug)]
+pub struct TaskReport {
+    graph: u64,
+    results: Vec<TaskResult>,
+    duration: Duration,
+}
+
+impl Display for TaskReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Task graph finished in {:.2?}", self.duration)?;
+        for result in &self.results {
+            match (&result.result, result.started) {
+                (Ok(_), _) => write!(f, "\n  ok      {} ({:.2?})", result.name, result.duration)?,
+                (Err(_), None) => write!(f, "\n  skipped {}", result.name)?,
+                (Err(err), Some(_)) => write!(
+                    f,
+                    "\n  failed  {} ({:.2?}): {}",
+                    result.name, result.duration, err
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TaskReport {
+    /// Panics if `id` came from a different graph.
+    pub fn get(&self, id: TaskId) -> &TaskResult {
+        assert_eq!(
+            id.graph, self.graph,
+            "developer error: `TaskId` from a different `TaskGraph`"
+        );
+        &self.results[id.index]
+    }
+
+    pub fn results(&self) -> &[TaskResult] {
+        &self.results
+    }
+
+    pub fn into_results(self) -> Vec<TaskResult> {
+        self.results
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.results.iter().all(TaskResult::succeeded)
+    }
+
+    /// How long the whole graph took to run.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+}
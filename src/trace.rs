@@ -0,0 +1,87 @@
+//! Spans for command executions, when the `tracing` feature is enabled. When
+//! it isn't, everything here compiles away to nothing, and the usual `log`
+//! records are all you get.
+
+use crate::{ExitStatus, Output};
+use std::path::Path;
+#[cfg(feature = "tracing")]
+use std::time::Instant;
+
+#[derive(Clone, Debug)]
+pub(crate) struct Trace {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    start: Instant,
+}
+
+impl Trace {
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn new(command: &str, cwd: Option<&Path>) -> Self {
+        Self {
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "command",
+                command,
+                cwd = ?cwd
+                    .map(ToOwned::to_owned)
+                    .or_else(|| std::env::current_dir().ok()),
+                pid = tracing::field::Empty,
+                exit_code = tracing::field::Empty,
+                signal = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+            ),
+            #[cfg(feature = "tracing")]
+            start: Instant::now(),
+        }
+    }
+
+    /// Runs `f` inside the span, so anything it logs is attributed to the
+    /// command.
+    pub fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        return self.span.in_scope(f);
+        #[cfg(not(feature = "tracing"))]
+        f()
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn spawned(&self, pid: u32) {
+        #[cfg(feature = "tracing")]
+        self.span.record("pid", pid);
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn exited(&self, status: Option<ExitStatus>) {
+        #[cfg(feature = "tracing")]
+        {
+            if let Some(code) = status.and_then(|status| status.code()) {
+                self.span.record("exit_code", code);
+            }
+            #[cfg(unix)]
+            {
+                use std::os::unix::process::ExitStatusExt as _;
+                if let Some(signal) = status.and_then(|status| status.signal()) {
+                    self.span.record("signal", signal);
+                }
+            }
+            self.span
+                .record("duration_ms", self.start.elapsed().as_millis() as u64);
+            self.span.in_scope(|| match status {
+                Some(status) => tracing::info!(success = status.success(), "command exited"),
+                None => tracing::warn!("failed to wait for command"),
+            });
+        }
+    }
+
+    /// Records each line of captured stderr as an event on the span.
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub fn stderr(&self, output: &Output) {
+        #[cfg(feature = "tracing")]
+        self.span.in_scope(|| {
+            for line in output.stderr_for_display().lines() {
+                tracing::debug!(stream = "stderr", "{}", line);
+            }
+        });
+    }
+}
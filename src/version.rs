@@ -0,0 +1,399 @@
+use crate::{Cause, Command, Error, Regex};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{OsStr, OsString},
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Mutex, MutexGuard, OnceLock, PoisonError},
+};
+
+/// A version number, as reported by some tool's `--version` output. Missing
+/// components are treated as `0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl FromStr for Version {
+    type Err = VersionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (major, minor, patch) = parse_components(s)?;
+        Ok(Self {
+            major,
+            minor: minor.unwrap_or_default(),
+            patch: patch.unwrap_or_default(),
+        })
+    }
+}
+
+impl Version {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+/// The error returned when a [`Version`] or [`VersionReq`] fails to parse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionParseError {
+    input: String,
+}
+
+impl Display for VersionParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} isn't a valid version or version requirement",
+            self.input
+        )
+    }
+}
+
+impl std::error::Error for VersionParseError {}
+
+fn parse_components(s: &str) -> Result<(u64, Option<u64>, Option<u64>), VersionParseError> {
+    let err = || VersionParseError {
+        input: s.to_owned(),
+    };
+    let mut parts = s.trim().split('.').map(|part| part.parse::<u64>());
+    let major = parts.next().and_then(Result::ok).ok_or_else(err)?;
+    let minor = parts
+        .next()
+        .map(|part| part.map_err(|_| err()))
+        .transpose()?;
+    let patch = parts
+        .next()
+        .map(|part| part.map_err(|_| err()))
+        .transpose()?;
+    if parts.next().is_some() {
+        Err(err())
+    } else {
+        Ok((major, minor, patch))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Exact => "=",
+            Self::Greater => ">",
+            Self::GreaterEq => ">=",
+            Self::Less => "<",
+            Self::LessEq => "<=",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Comparator {
+    op: Op,
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+}
+
+impl Display for Comparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.op.as_str(), self.major)?;
+        if let Some(minor) = self.minor {
+            write!(f, ".{}", minor)?;
+        }
+        if let Some(patch) = self.patch {
+            write!(f, ".{}", patch)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Comparator {
+    type Err = VersionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        // Two-character operators need to be checked first, since otherwise
+        // `>=` would be mistaken for `>`.
+        let (op, rest) = [
+            (">=", Op::GreaterEq),
+            ("<=", Op::LessEq),
+            (">", Op::Greater),
+            ("<", Op::Less),
+            ("=", Op::Exact),
+        ]
+        .iter()
+        .find_map(|(prefix, op)| s.strip_prefix(prefix).map(|rest| (*op, rest)))
+        .unwrap_or((Op::Exact, s));
+        let (major, minor, patch) = parse_components(rest).map_err(|_| VersionParseError {
+            input: s.to_owned(),
+        })?;
+        Ok(Self {
+            op,
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl Comparator {
+    fn matches(&self, version: Version) -> bool {
+        // Only compare as many components as the requirement specified, so
+        // that `=3.18` matches `3.18.2` and `>3.18` doesn't.
+        let truncated = (
+            version.major,
+            self.minor.map(|_| version.minor),
+            self.patch.map(|_| version.patch),
+        );
+        let ordering = truncated.cmp(&(self.major, self.minor, self.patch));
+        match self.op {
+            Op::Exact => ordering.is_eq(),
+            Op::Greater => ordering.is_gt(),
+            Op::GreaterEq => ordering.is_ge(),
+            Op::Less => ordering.is_lt(),
+            Op::LessEq => ordering.is_le(),
+        }
+    }
+}
+
+/// A requirement that a [`Version`] has to satisfy, written as a
+/// comma-separated list of comparisons like `">=3.18, <4"`. A bare version is
+/// treated as an exact match on however many components it specifies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VersionReq {
+    comparators: Vec<Comparator>,
+}
+
+impl Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, comparator) in self.comparators.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", comparator)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = VersionParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(|comparators| Self { comparators })
+    }
+}
+
+impl VersionReq {
+    pub fn matches(&self, version: Version) -> bool {
+        self.comparators
+            .iter()
+            .all(|comparator| comparator.matches(version))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CacheKey {
+    program: PathBuf,
+    args: Vec<OsString>,
+    env: Vec<(OsString, OsString)>,
+    pattern: String,
+}
+
+fn cache() -> MutexGuard<'static, HashMap<CacheKey, Version>> {
+    static CACHE: OnceLock<Mutex<HashMap<CacheKey, Version>>> = OnceLock::new();
+    CACHE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Find out what version of a tool is installed, and complain if it's not the
+/// one you wanted. By default, this runs `<program> --version` and takes the
+/// first thing that looks like a version number from stdout (or stderr, for
+/// the tools that insist on putting it there).
+///
+/// Results are cached per program, args, and env vars, so probing the same
+/// tool more than once won't spawn it more than once. Programs are told apart
+/// by where they're found on `PATH`, so changing `PATH` can get you a fresh
+/// probe.
+///
+/// Probing is read-only, so it happens even in dry-run mode.
+#[derive(Clone, Debug)]
+pub struct VersionProbe {
+    program: OsString,
+    args: Vec<OsString>,
+    env: Vec<(OsString, OsString)>,
+    pattern: Regex,
+}
+
+impl VersionProbe {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            program: program.as_ref().to_owned(),
+            args: vec!["--version".into()],
+            env: Vec::new(),
+            pattern: Regex::new(r"\d+\.\d+(?:\.\d+)?").expect("developer error: bad default regex"),
+        }
+    }
+
+    /// Replace the default `--version` arg with your own arg list.
+    pub fn with_args(mut self, args: impl IntoIterator<Item = impl AsRef<OsStr>>) -> Self {
+        self.args = args
+            .into_iter()
+            .map(|arg| arg.as_ref().to_owned())
+            .collect();
+        self
+    }
+
+    /// Set an env var on the command. Setting `PATH` changes where the program
+    /// is looked for, too.
+    pub fn with_env_var(mut self, key: impl AsRef<OsStr>, val: impl AsRef<OsStr>) -> Self {
+        self.env
+            .push((key.as_ref().to_owned(), val.as_ref().to_owned()));
+        self
+    }
+
+    /// Replace the default pattern used to find the version number. If the
+    /// pattern has a capture group, then the first group is used as the
+    /// version; otherwise, the whole match is.
+    pub fn with_pattern(mut self, pattern: Regex) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::impure(&self.program);
+        for (key, val) in &self.env {
+            command.add_env_var(key, val);
+        }
+        command.with_args(&self.args).with_always_run()
+    }
+
+    /// Where the program would be run from, or just its name if we can't
+    /// find it (in which case running it is going to fail anyway). Symlinks
+    /// aren't followed, since plenty of tools care what they're called.
+    fn resolve_program(&self) -> PathBuf {
+        let program = Path::new(&self.program);
+        if program.components().count() > 1 {
+            return env::current_dir()
+                .map(|cwd| cwd.join(program))
+                .unwrap_or_else(|_| program.to_owned());
+        }
+        let candidates = |dir: PathBuf| {
+            let path = dir.join(program);
+            #[cfg(windows)]
+            let with_exe = path
+                .extension()
+                .is_none()
+                .then(|| path.with_extension("exe"));
+            #[cfg(not(windows))]
+            let with_exe = None;
+            std::iter::once(path).chain(with_exe)
+        };
+        let path = self
+            .env
+            .iter()
+            .rev()
+            .find(|(key, _)| key == "PATH")
+            .map(|(_, val)| val.clone());
+        path.or_else(|| env::var_os("PATH"))
+            .and_then(|paths| {
+                env::split_paths(&paths)
+                    .flat_map(candidates)
+                    .find(|path| path.is_file())
+            })
+            .unwrap_or_else(|| program.to_owned())
+    }
+
+    fn cache_key(&self) -> CacheKey {
+        CacheKey {
+            program: self.resolve_program(),
+            args: self.args.clone(),
+            env: self.env.clone(),
+            pattern: self.pattern.as_str().to_owned(),
+        }
+    }
+
+    fn extract(&self, text: &str) -> Option<Version> {
+        self.pattern.captures_iter(text).find_map(|captures| {
+            captures
+                .get(1)
+                .or_else(|| captures.get(0))
+                .and_then(|m| m.as_str().parse().ok())
+        })
+    }
+
+    /// Run the command (unless we already have) and get the version from its
+    /// output.
+    pub fn probe(&self) -> crate::Result<Version> {
+        let key = self.cache_key();
+        if let Some(version) = cache().get(&key) {
+            log::debug!("using cached version {} for {:?}", version, self.program);
+            return Ok(*version);
+        }
+        let mut command = self.command();
+        let output = command.run_and_wait_for_output()?;
+        let version = [output.stdout(), output.stderr()]
+            .iter()
+            .find_map(|stream| self.extract(&String::from_utf8_lossy(stream)))
+            .ok_or_else(|| {
+                Error::new(
+                    command.display().to_owned(),
+                    Cause::VersionNotFound {
+                        pattern: self.pattern.as_str().to_owned(),
+                    },
+                )
+            })?;
+        log::info!("found version {} for {:?}", version, self.program);
+        cache().insert(key, version);
+        Ok(version)
+    }
+
+    /// The same as `probe`, but fails if the version doesn't satisfy `req`.
+    pub fn require(&self, req: &VersionReq) -> crate::Result<Version> {
+        let found = self.probe()?;
+        if req.matches(found) {
+            Ok(found)
+        } else {
+            Err(Error::new(
+                self.command().display().to_owned(),
+                Cause::VersionMismatch {
+                    program: self.program.to_string_lossy().into_owned(),
+                    found,
+                    required: req.clone(),
+                },
+            ))
+        }
+    }
+
+    /// Forget all cached versions, which is handy if you just installed or
+    /// upgraded something.
+    pub fn clear_cache() {
+        cache().clear();
+    }
+}
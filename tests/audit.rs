@@ -0,0 +1,154 @@
+#![cfg(unix)]
+
+use bossy::{AuditLog, AuditOutput, Command};
+use serde_json::Value;
+use std::{
+    env,
+    io::{self, Write},
+    os::unix::ffi::OsStrExt as _,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+// The audit log is global, so these can't run at the same time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+struct Recording {
+    buffer: Buffer,
+    _serial: MutexGuard<'static, ()>,
+}
+
+impl Recording {
+    fn start(output: AuditOutput) -> Self {
+        let serial = SERIAL.lock().unwrap_or_else(|err| err.into_inner());
+        let buffer = Buffer::default();
+        bossy::set_audit_log(Some(AuditLog::new(buffer.clone()).with_output(output)));
+        Self {
+            buffer,
+            _serial: serial,
+        }
+    }
+
+    fn records(self) -> Vec<Value> {
+        bossy::set_audit_log(None);
+        let bytes = self.buffer.0.lock().unwrap().clone();
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+#[test]
+fn records_have_every_field() {
+    let recording = Recording::start(AuditOutput::Truncated(3));
+    Command::impure_parse("sh -c")
+        .with_arg("echo hello; echo oops >&2; exit 3")
+        .run_and_wait_for_output()
+        .unwrap_err();
+    let records = recording.records();
+    assert_eq!(records.len(), 1);
+    let record = &records[0];
+    assert_eq!(record["program"], "sh");
+    assert_eq!(record["args"][0], "-c");
+    assert_eq!(record["args"][1], "echo hello; echo oops >&2; exit 3");
+    assert_eq!(record["cwd"], env::current_dir().unwrap().to_str().unwrap());
+    assert!(record["start_unix_ms"].as_u64().unwrap() > 0);
+    assert!(record["duration_ms"].is_u64());
+    assert!(record["pid"].as_u64().unwrap() > 0);
+    assert_eq!(record["exit_code"], 3);
+    assert_eq!(record["signal"], Value::Null);
+    assert_eq!(record["success"], false);
+    assert_eq!(record["detached"], false);
+    assert!(
+        record["error"].as_str().unwrap().contains("exit"),
+        "{}",
+        record
+    );
+    assert_eq!(record["stdout"]["len"], 6);
+    assert_eq!(record["stdout"]["head"], "hel");
+    assert_eq!(record["stdout"]["truncated"], true);
+    assert_eq!(record["stderr"]["head"], "oop");
+}
+
+#[test]
+fn env_is_a_diff_against_ours() {
+    let home = env::var_os("HOME").expect("HOME isn't set");
+    let recording = Recording::start(AuditOutput::None);
+    Command::impure("true")
+        .with_env_var("BOSSY_AUDIT_ADDED", "new")
+        .with_env_var("HOME", "/somewhere/else")
+        .with_env_var("PATH", env::var_os("PATH").unwrap())
+        .without_env_var("HOME_IS_NOT_SET")
+        .without_env_var("USER")
+        .run_and_wait()
+        .unwrap();
+    Command::pure("true")
+        .with_env_var("HOME", &home)
+        .run_and_wait()
+        .unwrap();
+    let records = recording.records();
+    let impure = &records[0]["env"];
+    assert_eq!(impure["cleared"], false);
+    assert_eq!(
+        impure["added"],
+        serde_json::json!({ "BOSSY_AUDIT_ADDED": "new" })
+    );
+    assert_eq!(
+        impure["changed"],
+        serde_json::json!({ "HOME": "/somewhere/else" })
+    );
+    let expected_removed = if env::var_os("USER").is_some() {
+        serde_json::json!(["USER"])
+    } else {
+        serde_json::json!([])
+    };
+    assert_eq!(impure["removed"], expected_removed);
+    let pure = &records[1]["env"];
+    assert_eq!(pure["cleared"], true);
+    assert_eq!(pure["added"], serde_json::json!({}));
+    assert_eq!(pure["changed"], serde_json::json!({}));
+    let removed = pure["removed"].as_array().unwrap();
+    assert!(removed.contains(&Value::from("PATH")));
+    assert!(!removed.contains(&Value::from("HOME")));
+}
+
+#[test]
+fn non_utf8_args_are_still_json() {
+    let recording = Recording::start(AuditOutput::None);
+    Command::impure("true")
+        .with_arg(std::ffi::OsStr::from_bytes(b"\xff\"\n"))
+        .run_and_wait()
+        .unwrap();
+    let records = recording.records();
+    assert_eq!(records[0]["args"][0], "\u{fffd}\"\n");
+}
+
+#[test]
+fn dropped_handles_are_recorded() {
+    let recording = Recording::start(AuditOutput::None);
+    let handle = Command::impure_parse("sleep 0.1").run().unwrap();
+    drop(handle);
+    let records = recording.records();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0]["program"], "sleep");
+    assert!(records[0]["pid"].as_u64().unwrap() > 0);
+    assert_eq!(records[0]["exit_code"], Value::Null);
+    assert!(records[0]["error"]
+        .as_str()
+        .unwrap()
+        .contains("dropped without being waited on"));
+}
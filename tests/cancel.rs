@@ -0,0 +1,45 @@
+#![cfg(unix)]
+
+use bossy::{CancellationToken, Cause, Command};
+use std::{
+    env, thread,
+    time::{Duration, Instant},
+};
+
+#[test]
+fn cancelling_stops_a_running_command() {
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(300));
+            token.cancel();
+        })
+    };
+    let start = Instant::now();
+    let err = Command::impure("sh")
+        .with_args(["-c", "echo started; exec sleep 30"])
+        .with_cancellation_token(token)
+        .run_and_wait_for_output()
+        .unwrap_err();
+    canceller.join().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    match err.cause() {
+        Cause::Cancelled(output) => assert_eq!(output.stdout_str().unwrap(), "started\n"),
+        cause => panic!("expected the command to be cancelled, but got {:?}", cause),
+    }
+}
+
+#[test]
+fn already_cancelled_commands_are_never_spawned() {
+    let marker = env::temp_dir().join(format!("bossy-cancel-test-{}", std::process::id()));
+    let token = CancellationToken::new();
+    token.cancel();
+    let err = Command::impure("touch")
+        .with_arg(&marker)
+        .with_cancellation_token(token)
+        .run_and_wait()
+        .unwrap_err();
+    assert!(matches!(err.cause(), Cause::Cancelled(_)), "{:?}", err);
+    assert!(!marker.exists());
+}
@@ -0,0 +1,27 @@
+#![cfg(unix)]
+
+use bossy::{ColorMode, Command};
+use std::env;
+
+fn term(mode: ColorMode) -> String {
+    Command::impure_parse("sh -c")
+        .with_arg("echo \"${TERM-unset}\"")
+        .with_color(mode)
+        .run_and_wait_for_string()
+        .unwrap()
+}
+
+// This changes our own `TERM`, so it's the only test in here.
+#[test]
+fn always_fixes_up_term() {
+    env::set_var("TERM", "screen");
+    assert_eq!(term(ColorMode::Always), "screen\n");
+    assert_eq!(term(ColorMode::Auto), "screen\n");
+    env::set_var("TERM", "dumb");
+    assert_eq!(term(ColorMode::Always), "xterm-256color\n");
+    assert_eq!(term(ColorMode::Auto), "dumb\n");
+    env::remove_var("TERM");
+    assert_eq!(term(ColorMode::Always), "xterm-256color\n");
+    assert_eq!(term(ColorMode::Auto), "unset\n");
+    assert_eq!(term(ColorMode::Never), "dumb\n");
+}
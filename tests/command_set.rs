@@ -0,0 +1,140 @@
+use bossy::{Command, CommandSet};
+
+#[test]
+fn workers_inherit_scoped_dry_run() {
+    let _dry_run = bossy::scoped_dry_run(true);
+    let results = CommandSet::new(2)
+        .with_commands(vec![Command::impure("false"), Command::impure("false")])
+        .run();
+    assert!(results.iter().all(Result::is_ok));
+}
+
+#[cfg(unix)]
+mod unix {
+    use bossy::{Cause, Command, CommandSet, FailureMode};
+    use std::{
+        fs,
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    fn sh(script: &str) -> Command {
+        Command::impure_parse("sh -c").with_arg(script)
+    }
+
+    fn scratch_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("bossy-command-set-{}-{}", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn results_are_in_input_order() {
+        // The first ones take the longest, so they finish last.
+        let commands = (0..4)
+            .map(|i| sh(&format!("sleep 0.{}; echo {}", 4 - i, i)))
+            .collect::<Vec<_>>();
+        let results = CommandSet::new(4).with_commands(commands).run();
+        let stdouts = results
+            .into_iter()
+            .map(|result| result.unwrap().stdout_str().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(stdouts, ["0\n", "1\n", "2\n", "3\n"]);
+    }
+
+    #[test]
+    fn max_parallel_limits_concurrency() {
+        let log = scratch_file("max-parallel");
+        let script = format!(
+            "echo start >> '{0}'; sleep 0.2; echo end >> '{0}'",
+            log.display()
+        );
+        let commands = (0..6).map(|_| sh(&script)).collect::<Vec<_>>();
+        let results = CommandSet::new(2).with_commands(commands).run();
+        assert!(results.iter().all(Result::is_ok));
+        let mut running = 0;
+        let mut most = 0;
+        for line in fs::read_to_string(&log).unwrap().lines() {
+            match line {
+                "start" => running += 1,
+                "end" => running -= 1,
+                _ => unreachable!(),
+            }
+            most = most.max(running);
+        }
+        fs::remove_file(&log).unwrap();
+        assert_eq!(most, 2);
+    }
+
+    fn failing_set(mode: FailureMode) -> CommandSet {
+        // Two workers: one gets stuck sleeping, the other fails right away
+        // and would otherwise go on to run the rest.
+        CommandSet::new(2)
+            .with_commands(vec![
+                Command::impure_parse("sleep 2"),
+                Command::impure("false"),
+                Command::impure("true"),
+                Command::impure("true"),
+            ])
+            .with_failure_mode(mode)
+    }
+
+    #[test]
+    fn fail_fast_cancels_and_skips_the_rest() {
+        let start = Instant::now();
+        let results = failing_set(FailureMode::FailFast).run();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(matches!(
+            results[0].as_ref().unwrap_err().cause(),
+            Cause::Cancelled(_)
+        ));
+        assert!(matches!(
+            results[1].as_ref().unwrap_err().cause(),
+            Cause::CommandFailedWithOutput(_)
+        ));
+        for result in &results[2..] {
+            assert!(matches!(
+                result.as_ref().unwrap_err().cause(),
+                Cause::Skipped
+            ));
+        }
+    }
+
+    #[test]
+    fn fail_fast_reports_the_real_failure_separately() {
+        let failures = failing_set(FailureMode::FailFast)
+            .run_and_check()
+            .unwrap_err();
+        let errors = failures
+            .errors()
+            .iter()
+            .map(|(i, _)| *i)
+            .collect::<Vec<_>>();
+        let aborted = failures
+            .aborted()
+            .iter()
+            .map(|(i, _)| *i)
+            .collect::<Vec<_>>();
+        assert_eq!(errors, [1]);
+        assert_eq!(aborted, [0, 2, 3]);
+    }
+
+    #[test]
+    fn keep_going_runs_everything() {
+        let start = Instant::now();
+        let results = failing_set(FailureMode::KeepGoing).run();
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1].as_ref().unwrap_err().cause(),
+            Cause::CommandFailedWithOutput(_)
+        ));
+        assert!(results[2..].iter().all(Result::is_ok));
+        let failures = failing_set(FailureMode::KeepGoing)
+            .run_and_check()
+            .unwrap_err();
+        assert_eq!(failures.errors().len(), 1);
+        assert!(failures.aborted().is_empty());
+    }
+}
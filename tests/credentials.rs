@@ -0,0 +1,38 @@
+#![cfg(unix)]
+
+use bossy::{Cause, Command};
+
+#[test]
+fn missing_user_is_user_not_found() {
+    let err = Command::impure("true")
+        .with_user("bossy-test-no-such-user")
+        .unwrap_err();
+    assert!(
+        matches!(err.cause(), Cause::UserNotFound { user } if user == "bossy-test-no-such-user"),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn uid_without_gid_is_refused_in_roots_group() {
+    if unsafe { libc::getegid() } != 0 {
+        eprintln!("skipping, since our group isn't root's");
+        return;
+    }
+    let err = Command::impure("true")
+        .with_uid(65534)
+        .run_and_wait()
+        .unwrap_err();
+    assert!(
+        matches!(err.cause(), Cause::SetUidFailed { uid: 65534, .. }),
+        "{:?}",
+        err
+    );
+    let output = Command::impure("id")
+        .with_uid(65534)
+        .with_gid(65534)
+        .run_and_wait_for_output()
+        .unwrap();
+    assert!(output.stdout_str().unwrap().starts_with("uid=65534"));
+}
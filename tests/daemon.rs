@@ -0,0 +1,56 @@
+#![cfg(unix)]
+
+use bossy::{AuditLog, Command, DaemonOptions};
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn audit_record_describes_the_daemon() {
+    let buffer = Buffer::default();
+    bossy::set_audit_log(Some(AuditLog::new(buffer.clone())));
+    let daemon = Command::impure("sleep")
+        .with_arg("30")
+        .run_as_daemon(&DaemonOptions::new())
+        .unwrap();
+    bossy::set_audit_log(None);
+    daemon.stop(Duration::from_secs(5)).unwrap();
+    let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let records = log
+        .lines()
+        .filter(|line| line.starts_with("{\"program\":\"sleep\""))
+        .collect::<Vec<_>>();
+    assert_eq!(records.len(), 1, "{}", log);
+    assert!(
+        records[0].contains(&format!("\"pid\":{},", daemon.pid())),
+        "{}",
+        records[0]
+    );
+    assert!(records[0].contains("\"detached\":true"), "{}", records[0]);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn daemon_can_detach_under_seccomp_after_running_normally() {
+    let mut command =
+        Command::impure("true").with_seccomp(bossy::SeccompProfile::no_exec_children());
+    command.run_and_wait().unwrap();
+    // Detaching forks, which the filter would deny if it were already
+    // installed.
+    command.run_as_daemon(&DaemonOptions::new()).unwrap();
+}
@@ -0,0 +1,81 @@
+#![cfg(unix)]
+
+use bossy::{Command, Exit, PropagateExit as _};
+use std::process::{ExitCode, Termination as _};
+
+// The commands here are this test binary itself, running `child` with this
+// set to the script for it to run and exit like.
+const CHILD_VAR: &str = "BOSSY_EXIT_CHILD";
+
+fn sh(script: &str) -> Command {
+    Command::impure_parse("sh -c").with_arg(script)
+}
+
+#[test]
+fn child() {
+    if let Ok(script) = std::env::var(CHILD_VAR) {
+        // This only returns if the command succeeded.
+        let code = Exit::from(sh(&script).run_and_wait()).report();
+        assert_eq!(code, ExitCode::SUCCESS);
+        std::process::exit(0);
+    }
+}
+
+fn exit_like(script: &str) -> bossy::Result<bossy::Output> {
+    Command::impure(std::env::current_exe().unwrap())
+        .with_args(["--exact", "child", "--nocapture", "--test-threads=1"])
+        .with_env_var(CHILD_VAR, script)
+        .run_and_wait_for_output()
+}
+
+#[test]
+fn exit_codes_are_what_shells_say() {
+    let status = sh("exit 7").run_and_wait().unwrap_err().status().unwrap();
+    assert_eq!(status.exit_code(), 7);
+    let status = sh("kill -TERM $$")
+        .run_and_wait()
+        .unwrap_err()
+        .status()
+        .unwrap();
+    assert_eq!(status.exit_code(), 128 + libc::SIGTERM);
+    let status = sh("kill -KILL $$")
+        .run_and_wait()
+        .unwrap_err()
+        .status()
+        .unwrap();
+    assert_eq!(status.exit_code(), 128 + libc::SIGKILL);
+}
+
+#[test]
+fn errors_without_a_status_get_shell_codes() {
+    let err = Command::impure("bossy-does-not-exist")
+        .run_and_wait()
+        .unwrap_err();
+    assert_eq!(err.exit_code(), 127);
+    // A directory is never executable.
+    let err = Command::impure(std::env::temp_dir())
+        .run_and_wait()
+        .unwrap_err();
+    assert_eq!(err.exit_code(), 126);
+}
+
+#[test]
+fn exit_propagates_codes() {
+    let err = exit_like("echo bye >&2; exit 7").unwrap_err();
+    assert_eq!(err.code(), Some(7));
+    let stderr = err.output().unwrap().stderr_str().unwrap();
+    assert!(stderr.contains("error: "), "{}", stderr);
+    assert!(stderr.contains("bye"), "{}", stderr);
+}
+
+#[test]
+fn exit_propagates_signals() {
+    let err = exit_like("kill -TERM $$").unwrap_err();
+    assert_eq!(err.signal(), Some(libc::SIGTERM));
+}
+
+#[test]
+fn exit_succeeds_quietly() {
+    let output = exit_like("true").unwrap();
+    assert!(!output.stderr_str().unwrap().contains("error: "));
+}
@@ -0,0 +1,62 @@
+#![cfg(unix)]
+
+use bossy::{Cause, Command};
+use std::{
+    io::{BufRead as _, BufReader, Read as _, Write as _},
+    os::unix::net::UnixStream,
+};
+
+fn sh(script: &str) -> Command {
+    Command::impure_parse("sh -c").with_arg(script)
+}
+
+#[test]
+fn stdio_fds_are_an_error() {
+    let (ours, _theirs) = UnixStream::pair().unwrap();
+    for child_fd in 0..=2 {
+        let err = Command::impure("true")
+            .with_fd(child_fd, &ours)
+            .unwrap_err();
+        assert!(matches!(err.cause(), Cause::FdFailed { fd, .. } if *fd == child_fd));
+        let err = Command::impure("true").with_pipe_fd(child_fd).unwrap_err();
+        assert!(matches!(err.cause(), Cause::FdFailed { fd, .. } if *fd == child_fd));
+    }
+}
+
+#[test]
+fn pipe_fd_goes_both_ways() {
+    let mut handle = sh("read line <&3; echo \"got $line\" >&3")
+        .with_pipe_fd(3)
+        .unwrap()
+        .run()
+        .unwrap();
+    let pipe = handle.take_pipe_fd(3).unwrap();
+    (&pipe).write_all(b"ping\n").unwrap();
+    let mut reply = String::new();
+    BufReader::new(&pipe).read_line(&mut reply).unwrap();
+    assert_eq!(reply, "got ping\n");
+    handle.wait().unwrap();
+}
+
+#[test]
+fn socket_activation_sets_listen_vars() {
+    let (first, mut first_peer) = UnixStream::pair().unwrap();
+    let (second, mut second_peer) = UnixStream::pair().unwrap();
+    let mut handle = sh("echo \"$LISTEN_PID $LISTEN_FDS\"; echo three >&3; echo four >&4")
+        .with_socket_activation([&first, &second])
+        .unwrap()
+        .with_stdout_piped()
+        .run()
+        .unwrap();
+    let pid = handle.pid().unwrap();
+    drop((first, second));
+    let output = handle.wait_for_output().unwrap();
+    assert_eq!(output.stdout_str().unwrap(), format!("{} 2\n", pid));
+    let read = |peer: &mut UnixStream| {
+        let mut text = String::new();
+        peer.read_to_string(&mut text).unwrap();
+        text
+    };
+    assert_eq!(read(&mut first_peer), "three\n");
+    assert_eq!(read(&mut second_peer), "four\n");
+}
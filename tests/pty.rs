@@ -0,0 +1,14 @@
+#![cfg(target_os = "linux")]
+
+use bossy::{Command, PtySize};
+
+#[test]
+fn strip_ansi_can_be_set_before_pty() {
+    let output = Command::impure("printf")
+        .with_arg("\\033[31mred\\033[0m")
+        .with_pty_strip_ansi()
+        .with_pty(PtySize::new(24, 80))
+        .run_and_wait_for_output()
+        .unwrap();
+    assert_eq!(output.stdout_str().unwrap(), "red");
+}
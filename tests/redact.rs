@@ -0,0 +1,68 @@
+#![cfg(unix)]
+
+use bossy::{ColorMode, Command, Regex};
+use std::sync::Mutex;
+
+static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct Collector;
+
+impl log::Log for Collector {
+    fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        LOGS.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn debug_output_is_redacted() {
+    let command = || {
+        Command::impure("sh")
+            .with_args(["-c", "echo hunter2; echo hunter2 >&2; exit $0"])
+            .with_redaction_pattern(Regex::new("hunter2").unwrap())
+    };
+    let output = command().with_arg("0").run_and_wait_for_output().unwrap();
+    let debug = format!("{:?}", output);
+    assert!(!debug.contains("hunter2"), "{}", debug);
+    assert!(debug.contains(bossy::REDACTED), "{}", debug);
+    let err = command()
+        .with_arg("1")
+        .run_and_wait_for_output()
+        .unwrap_err();
+    let debug = format!("{:?}", err);
+    assert!(!debug.contains("hunter2"), "{}", debug);
+    assert!(debug.contains(bossy::REDACTED), "{}", debug);
+}
+
+#[test]
+fn setters_log_redacted_values() {
+    log::set_logger(&Collector).unwrap();
+    log::set_max_level(log::LevelFilter::Debug);
+    // Everything these setters log comes from values that match this.
+    let secrets = Regex::new("root|always|hunter2").unwrap();
+    Command::impure("true")
+        .with_redaction_pattern(secrets.clone())
+        .with_user("root")
+        .unwrap()
+        .with_color(ColorMode::Always)
+        .with_dry_run_output(0, "hunter2", "hunter2");
+    let logs = LOGS.lock().unwrap();
+    // The other test logs too, and so does adding the pattern itself.
+    let logs = logs
+        .iter()
+        .filter(|line| line.ends_with("command \"true\"") && !line.contains("redaction pattern"))
+        .collect::<Vec<_>>();
+    assert!(
+        logs.iter().any(|line| line.contains("LOGNAME")),
+        "{:?}",
+        logs
+    );
+    for line in logs {
+        assert!(!secrets.is_match(line), "{}", line);
+    }
+}
@@ -0,0 +1,122 @@
+#![cfg(unix)]
+
+use bossy::{Command, Error};
+
+// The cwd is wherever the tests are run from, so it's swapped out.
+fn snapshot(err: &Error, stderr_lines: usize) -> String {
+    let cwd = std::env::current_dir().unwrap();
+    err.report()
+        .with_stderr_lines(stderr_lines)
+        .to_string()
+        .replace(&format!("cwd: {}\n", cwd.display()), "cwd: <cwd>\n")
+}
+
+fn sh(script: &str) -> Command {
+    Command::impure_parse("sh -c").with_arg(script)
+}
+
+#[test]
+fn not_found() {
+    let err = Command::pure("bossy-does-not-exist")
+        .with_env_var("FOO", "bar")
+        .run_and_wait()
+        .unwrap_err();
+    assert_eq!(
+        snapshot(&err, 10),
+        r#"error: Failed to spawn child process for command "bossy-does-not-exist": No such file or directory (os error 2)
+  ┌ command: bossy-does-not-exist
+  │     cwd: <cwd>
+  │     env: FOO=bar
+  │          (everything else cleared)
+  └
+  help: "bossy-does-not-exist" wasn't found; make sure it's installed and on your `PATH`.
+  help: The command's environment was cleared, so `PATH` has to be set on it too.
+"#
+    );
+}
+
+#[test]
+fn permission_denied() {
+    // A directory is never executable.
+    let err = Command::impure("/").run_and_wait().unwrap_err();
+    assert_eq!(
+        snapshot(&err, 10),
+        r#"error: Failed to spawn child process for command "/": Permission denied (os error 13)
+  ┌ command: /
+  │     cwd: <cwd>
+  └
+  help: "/" isn't executable; check its permissions (`chmod +x`) and that its filesystem isn't mounted `noexec`.
+"#
+    );
+}
+
+#[test]
+fn killed_by_signal() {
+    let err = sh("echo dying >&2; kill -KILL $$")
+        .run_and_wait_for_output()
+        .unwrap_err();
+    assert_eq!(
+        snapshot(&err, 10),
+        r#"error: Command "sh -c echo dying >&2; kill -KILL $$" didn't complete successfully.
+  ┌ command: sh -c 'echo dying >&2; kill -KILL $$'
+  │     cwd: <cwd>
+  │  status: killed by signal 9 (SIGKILL)
+  ├ stderr
+  │ 1 │ dying
+  └
+  help: `SIGKILL` can't be caught, so this was probably a timeout, someone killing it, or the out-of-memory killer.
+"#
+    );
+}
+
+#[test]
+fn stderr_tail_is_truncated() {
+    let err = sh(r"printf 'line %s\n' 1 2 3 4 5 6 7 8 9 10 11 12 >&2; exit 2")
+        .run_and_wait_for_output()
+        .unwrap_err();
+    // The headline is `Debug`-formatted, so the backslash is escaped there.
+    let header = r#"error: Command "sh -c printf 'line %s\\n' 1 2 3 4 5 6 7 8 9 10 11 12 >&2; exit 2" didn't complete successfully.
+  ┌ command: sh -c 'printf '\''line %s\n'\'' 1 2 3 4 5 6 7 8 9 10 11 12 >&2; exit 2'
+  │     cwd: <cwd>
+  │  status: exited with code 2
+"#;
+    assert_eq!(
+        snapshot(&err, 10),
+        format!(
+            "{}{}",
+            header,
+            r#"  ├ stderr (last 10 of 12 lines)
+  │  3 │ line 3
+  │  4 │ line 4
+  │  5 │ line 5
+  │  6 │ line 6
+  │  7 │ line 7
+  │  8 │ line 8
+  │  9 │ line 9
+  │ 10 │ line 10
+  │ 11 │ line 11
+  │ 12 │ line 12
+  └
+"#
+        )
+    );
+    assert_eq!(
+        snapshot(&err, 3),
+        format!(
+            "{}{}",
+            header,
+            r#"  ├ stderr (last 3 of 12 lines)
+  │ 10 │ line 10
+  │ 11 │ line 11
+  │ 12 │ line 12
+  └
+"#
+        )
+    );
+}
+
+#[test]
+fn empty_stderr_is_mentioned() {
+    let err = sh("exit 1").run_and_wait_for_output().unwrap_err();
+    assert!(snapshot(&err, 10).ends_with("  ├ stderr was empty\n  └\n"));
+}
@@ -0,0 +1,37 @@
+#![cfg(unix)]
+
+use bossy::{Cause, Command, Resource};
+
+#[test]
+fn cpu_time_limit_is_reported() {
+    let err = Command::impure_parse("sh -c")
+        .with_arg("while :; do :; done")
+        .with_rlimit(Resource::CoreSize, 0, 0)
+        .with_rlimit(Resource::CpuTime, 1, 5)
+        .run_and_wait()
+        .unwrap_err();
+    assert_eq!(err.signal(), Some(libc::SIGXCPU));
+    assert_eq!(err.exceeded_rlimit(), Some(Resource::CpuTime));
+    assert!(err
+        .report()
+        .to_string()
+        .contains("killed for exceeding its CPU time limit"));
+}
+
+#[test]
+fn failing_to_set_a_limit_says_which() {
+    // A soft limit over the hard one is never allowed, even for root.
+    let err = Command::impure("true")
+        .with_rlimit(Resource::CoreSize, 0, 0)
+        .with_rlimit(Resource::OpenFiles, 64, 32)
+        .run_and_wait()
+        .unwrap_err();
+    match err.cause() {
+        Cause::RlimitFailed { resource, source } => {
+            assert_eq!(*resource, Resource::OpenFiles);
+            assert_eq!(source.raw_os_error(), Some(libc::EINVAL));
+        }
+        cause => panic!("wrong cause: {:?}", cause),
+    }
+    assert!(err.to_string().contains("open files limit"), "{}", err);
+}
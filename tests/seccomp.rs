@@ -0,0 +1,68 @@
+#![cfg(target_os = "linux")]
+
+use bossy::{Command, SeccompProfile};
+use std::io;
+
+// The commands here are this test binary itself, running `child` with this
+// set to the socket family to try.
+const CHILD_VAR: &str = "BOSSY_SECCOMP_CHILD";
+
+#[test]
+fn child() {
+    let family = match std::env::var(CHILD_VAR) {
+        Ok(family) => family.parse().unwrap(),
+        Err(_) => return,
+    };
+    let fd = unsafe { libc::socket(family, libc::SOCK_STREAM, 0) };
+    if fd == -1 {
+        println!(
+            "socket: errno={}",
+            io::Error::last_os_error().raw_os_error().unwrap()
+        );
+    } else {
+        println!("socket: ok");
+    }
+}
+
+fn try_socket(family: libc::c_int, profile: SeccompProfile) -> bossy::Result<String> {
+    Command::impure(std::env::current_exe().unwrap())
+        .with_args(["--exact", "child", "--nocapture", "--test-threads=1"])
+        .with_env_var(CHILD_VAR, family.to_string())
+        .with_seccomp(profile)
+        .run_and_wait_for_str(|stdout| {
+            // The test harness's output can end up on the same line.
+            let (_, rest) = stdout
+                .split_once("socket: ")
+                .expect("child didn't say how it went");
+            rest.lines().next().unwrap_or_default().to_owned()
+        })
+}
+
+#[test]
+fn no_network_denies_inet_sockets() {
+    let result = try_socket(libc::AF_INET, SeccompProfile::no_network()).unwrap();
+    assert_eq!(result, format!("errno={}", libc::EPERM));
+    let result = try_socket(libc::AF_INET6, SeccompProfile::no_network()).unwrap();
+    assert_eq!(result, format!("errno={}", libc::EPERM));
+}
+
+#[test]
+fn no_network_allows_unix_sockets() {
+    let result = try_socket(libc::AF_UNIX, SeccompProfile::no_network()).unwrap();
+    assert_eq!(result, "ok");
+}
+
+#[test]
+fn kill_on_violation_gets_sigsys() {
+    let err = try_socket(
+        libc::AF_INET,
+        SeccompProfile::no_network().with_kill_on_violation(),
+    )
+    .unwrap_err();
+    assert_eq!(err.signal(), Some(libc::SIGSYS));
+    assert!(
+        err.note().unwrap().contains("\"no-network\""),
+        "{:?}",
+        err.note()
+    );
+}
@@ -0,0 +1,81 @@
+#![cfg(all(unix, feature = "serde"))]
+
+use bossy::{Cause, Command, Error};
+
+fn round_trip(err: &Error) -> Error {
+    let json = serde_json::to_string(err).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+// Whatever else changes, it has to look the same on the other end.
+fn assert_renders_the_same(err: &Error) -> Error {
+    let copy = round_trip(err);
+    assert_eq!(copy.to_string(), err.to_string());
+    assert_eq!(copy.report().to_string(), err.report().to_string());
+    assert_eq!(copy.command(), err.command());
+    assert_eq!(copy.status(), err.status());
+    copy
+}
+
+fn sh(script: &str) -> Command {
+    Command::impure_parse("sh -c").with_arg(script)
+}
+
+#[test]
+fn spawn_failure() {
+    let err = Command::impure("bossy-does-not-exist")
+        .run_and_wait()
+        .unwrap_err();
+    let copy = assert_renders_the_same(&err);
+    match copy.cause() {
+        Cause::SpawnFailed(source) => {
+            assert_eq!(source.raw_os_error(), Some(libc::ENOENT));
+            assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+        }
+        cause => panic!("wrong cause: {:?}", cause),
+    }
+}
+
+#[test]
+fn non_zero_exit_with_output() {
+    let err = sh("echo out; echo err >&2; exit 3")
+        .run_and_wait_for_output()
+        .unwrap_err();
+    let copy = assert_renders_the_same(&err);
+    assert_eq!(copy.code(), Some(3));
+    let output = copy.output().unwrap();
+    assert_eq!(output.stdout(), b"out\n");
+    assert_eq!(output.stderr(), b"err\n");
+}
+
+#[test]
+fn killed_by_signal() {
+    let err = sh("kill -TERM $$").run_and_wait().unwrap_err();
+    let copy = assert_renders_the_same(&err);
+    assert_eq!(copy.signal(), Some(libc::SIGTERM));
+    assert_eq!(copy.code(), None);
+}
+
+#[test]
+fn invalid_utf8() {
+    let err = sh(r"printf 'ok\377'")
+        .run_and_wait_for_string()
+        .unwrap_err();
+    let copy = assert_renders_the_same(&err);
+    match copy.cause() {
+        Cause::InvalidUtf8 { source, .. } => {
+            assert_eq!(source.valid_up_to(), 2);
+            assert_eq!(source.error_len(), Some(1));
+        }
+        cause => panic!("wrong cause: {:?}", cause),
+    }
+}
+
+#[test]
+fn non_utf8_stderr() {
+    let err = sh(r"printf 'bad \377 bytes' >&2; exit 1")
+        .run_and_wait_for_output()
+        .unwrap_err();
+    let copy = assert_renders_the_same(&err);
+    assert_eq!(copy.output().unwrap().stderr(), b"bad \xff bytes");
+}
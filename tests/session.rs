@@ -0,0 +1,105 @@
+#![cfg(unix)]
+
+use bossy::{Cause, Command, Regex, Session};
+use std::time::{Duration, Instant};
+
+fn session(script: &str) -> Session {
+    Command::impure_parse("sh -c")
+        .with_arg(script)
+        .run_interactive()
+        .unwrap()
+}
+
+// Short enough to not slow things down, but only used where waiting is the
+// point.
+const TIMEOUT: Duration = Duration::from_millis(300);
+
+fn assert_timed_out(err: &bossy::Error, expected_buffer: &str) {
+    match err.cause() {
+        Cause::ExpectTimedOut {
+            timeout, buffer, ..
+        } => {
+            assert_eq!(*timeout, TIMEOUT);
+            assert_eq!(buffer, expected_buffer);
+        }
+        cause => panic!("wrong cause: {:?}", cause),
+    }
+}
+
+fn stop(mut session: Session) {
+    session.handle().kill().unwrap();
+    session.wait().unwrap_err();
+}
+
+#[test]
+fn expect_returns_what_came_before() {
+    let mut session =
+        session("printf 'name? '; read name; echo \"hello $name\"; sleep 0.1; echo bye >&2");
+    assert_eq!(session.expect("name? ").unwrap(), "");
+    session.send_line("bob").unwrap();
+    assert_eq!(session.expect("bob").unwrap(), "hello ");
+    assert_eq!(session.expect("bye").unwrap(), "\n");
+    assert_eq!(session.expect_eof().unwrap(), "\n");
+    session.wait().unwrap();
+}
+
+#[test]
+fn expect_times_out() {
+    let mut session = session("echo partial output; exec sleep 30");
+    session.expect("partial").unwrap();
+    let start = Instant::now();
+    let err = session.set_timeout(TIMEOUT).expect("never").unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_timed_out(&err, " output\n");
+    assert_eq!(err.session_buffer(), Some(" output\n"));
+    stop(session);
+}
+
+#[test]
+fn expect_fails_at_eof() {
+    let mut session = session("echo done");
+    let err = session.expect("never").unwrap_err();
+    match err.cause() {
+        Cause::ExpectEof { expected, buffer } => {
+            assert_eq!(expected, "\"never\"");
+            assert_eq!(buffer, "done\n");
+        }
+        cause => panic!("wrong cause: {:?}", cause),
+    }
+    session.wait().unwrap();
+}
+
+#[test]
+fn expect_regex_captures_groups() {
+    let mut session = session("echo 'progress: 42%'; echo 'progress: done'");
+    let regex = Regex::new(r"progress: (\d+)%(x)?").unwrap();
+    let found = session.expect_regex(&regex).unwrap();
+    assert_eq!(found.before(), "");
+    assert_eq!(found.matched(), "progress: 42%");
+    assert_eq!(found.group(1), Some("42"));
+    assert_eq!(found.group(2), None);
+    assert_eq!(session.buffer(), "\nprogress: done\n");
+    session.expect_eof().unwrap();
+    session.wait().unwrap();
+}
+
+#[test]
+fn expect_regex_times_out() {
+    let mut session = session("echo 'progress: ?'; exec sleep 30");
+    session.expect("progress:").unwrap();
+    let err = session
+        .set_timeout(TIMEOUT)
+        .expect_regex(&Regex::new(r"\d+%").unwrap())
+        .unwrap_err();
+    assert_timed_out(&err, " ?\n");
+    stop(session);
+}
+
+#[test]
+fn expect_eof_times_out() {
+    let mut session = session("echo still here; exec sleep 30");
+    session.expect("still").unwrap();
+    let err = session.set_timeout(TIMEOUT).expect_eof().unwrap_err();
+    assert_timed_out(&err, " here\n");
+    stop(session);
+}
@@ -0,0 +1,71 @@
+#![cfg(unix)]
+
+use bossy::Command;
+use std::{
+    mem, ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+static OURS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn count(_: libc::c_int) {
+    OURS.fetch_add(1, Ordering::SeqCst);
+}
+
+fn current_handler() -> libc::sighandler_t {
+    unsafe {
+        let mut action = mem::zeroed::<libc::sigaction>();
+        assert_eq!(libc::sigaction(libc::SIGTERM, ptr::null(), &mut action), 0);
+        action.sa_sigaction
+    }
+}
+
+// This changes our own `SIGTERM` handler, so it's the only test in here.
+#[test]
+fn sigterm_is_forwarded_and_our_handler_restored() {
+    // If forwarding doesn't work, this is what gets the signal instead of the
+    // default handler killing the whole test.
+    let ours = count as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        let mut action = mem::zeroed::<libc::sigaction>();
+        action.sa_sigaction = ours;
+        libc::sigemptyset(&mut action.sa_mask);
+        assert_eq!(libc::sigaction(libc::SIGTERM, &action, ptr::null_mut()), 0);
+    }
+
+    let ready = std::env::temp_dir().join(format!("bossy-signal-ready-{}", std::process::id()));
+    let _ = std::fs::remove_file(&ready);
+    let sender = {
+        let ready = ready.clone();
+        thread::spawn(move || {
+            while !ready.exists() {
+                thread::sleep(Duration::from_millis(10));
+            }
+            unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+        })
+    };
+    let output = Command::impure_parse("sh -c")
+        .with_arg(r#"trap 'echo got TERM; exit 0' TERM; touch "$1"; while :; do sleep 0.05; done"#)
+        .with_args(["sh".as_ref(), ready.as_os_str()])
+        .with_signal_forwarding([libc::SIGTERM])
+        .run_and_wait_for_output()
+        .unwrap();
+    sender.join().unwrap();
+    std::fs::remove_file(&ready).unwrap();
+    assert_eq!(output.stdout_str().unwrap(), "got TERM\n");
+    assert_eq!(OURS.load(Ordering::SeqCst), 0);
+
+    assert_eq!(current_handler(), ours);
+    unsafe { libc::kill(libc::getpid(), libc::SIGTERM) };
+    // Signals sent to ourselves are delivered before `kill` returns, but
+    // maybe not to this thread.
+    for _ in 0..100 {
+        if OURS.load(Ordering::SeqCst) == 1 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(OURS.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,36 @@
+use bossy::{Command, Restart, ServiceState, Supervisor};
+use std::{thread, time::Duration};
+
+#[test]
+fn services_inherit_scoped_dry_run() {
+    let _dry_run = bossy::scoped_dry_run(true);
+    let mut supervisor =
+        Supervisor::new().with_command("false", Command::impure("false"), Restart::OnFailure);
+    supervisor.start();
+    while supervisor.is_active() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let status = supervisor.status();
+    assert_eq!(status[0].state(), ServiceState::Exited);
+    assert!(status[0].last_error().is_none());
+}
+
+#[test]
+fn supervisor_can_start_again_after_shutdown() {
+    let mut supervisor = Supervisor::new().with_command(
+        "sleep",
+        Command::impure("sleep").with_arg("30"),
+        Restart::Never,
+    );
+    supervisor.start();
+    supervisor.shutdown();
+    supervisor
+        .add_command("true", Command::impure("true"), Restart::Never)
+        .start();
+    while supervisor.is_active() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    let status = supervisor.status();
+    assert_eq!(status[0].state(), ServiceState::Stopped);
+    assert_eq!(status[1].state(), ServiceState::Exited);
+}
@@ -0,0 +1,68 @@
+use bossy::{Command, TaskGraph};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+#[test]
+fn panicking_deferred_task_panics_run() {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut graph = TaskGraph::new();
+        let first = graph.add_task("first", Command::impure("true"), &[]);
+        graph.add_deferred_task("second", || panic!("couldn't build command"), &[first]);
+        graph.add_task("unrelated", Command::impure("true"), &[]);
+        let result = panic::catch_unwind(AssertUnwindSafe(move || graph.run()));
+        tx.send(result.is_err()).unwrap();
+    });
+    let panicked = rx
+        .recv_timeout(Duration::from_secs(10))
+        .expect("`TaskGraph::run` hung after a task panicked");
+    assert!(panicked);
+}
+
+#[test]
+fn tasks_inherit_scoped_dry_run() {
+    let _dry_run = bossy::scoped_dry_run(true);
+    let mut graph = TaskGraph::new();
+    let first = graph.add_task("first", Command::impure("false"), &[]);
+    graph.add_task("second", Command::impure("false"), &[first]);
+    let report = graph.run().unwrap();
+    assert!(report.succeeded());
+}
+
+#[test]
+fn dot_only_escapes_quotes_and_backslashes() {
+    let mut graph = TaskGraph::new();
+    let first = graph.add_task("say \"héllo\"", Command::impure("true"), &[]);
+    graph.add_task("C:\\tab\there", Command::impure("true"), &[first]);
+    assert_eq!(
+        graph.to_dot(),
+        "digraph {\n    \"say \\\"héllo\\\"\" [tooltip=\"true\"];\n    \"C:\\\\tab\there\" [tooltip=\"true\"];\n    \"say \\\"héllo\\\"\" -> \"C:\\\\tab\there\";\n}"
+    );
+}
+
+#[test]
+#[should_panic(expected = "`TaskId` from a different `TaskGraph`")]
+fn ids_from_other_graphs_are_rejected() {
+    let mut other = TaskGraph::new();
+    let foreign = other.add_task("foreign", Command::impure("true"), &[]);
+    // The index is in range here too, so only the graph can give it away.
+    let mut graph = TaskGraph::new();
+    let first = graph.add_task("first", Command::impure("true"), &[]);
+    graph.add_task("second", Command::impure("true"), &[]);
+    graph.add_dependency(first, foreign);
+}
+
+#[test]
+#[should_panic(expected = "`TaskId` from a different `TaskGraph`")]
+fn reports_reject_ids_from_other_graphs() {
+    let _dry_run = bossy::scoped_dry_run(true);
+    let mut other = TaskGraph::new();
+    let foreign = other.add_task("foreign", Command::impure("true"), &[]);
+    let mut graph = TaskGraph::new();
+    graph.add_task("first", Command::impure("true"), &[]);
+    graph.run().unwrap().get(foreign);
+}
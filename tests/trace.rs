@@ -0,0 +1,116 @@
+#![cfg(all(unix, feature = "tracing"))]
+
+use bossy::Command;
+use std::{
+    collections::BTreeMap,
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Metadata, Subscriber,
+};
+
+#[derive(Debug, Default)]
+struct State {
+    fields: BTreeMap<String, String>,
+    // What happened to the span, in order.
+    happenings: Vec<String>,
+}
+
+struct Fields<'a>(&'a mut BTreeMap<String, String>);
+
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{:?}", value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.to_owned());
+    }
+}
+
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<State>>);
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut state = self.0.lock().unwrap();
+        assert_eq!(span.metadata().name(), "command");
+        span.record(&mut Fields(&mut state.fields));
+        Id::from_u64(1)
+    }
+
+    fn record(&self, _: &Id, values: &Record<'_>) {
+        let mut state = self.0.lock().unwrap();
+        let mut recorded = BTreeMap::new();
+        values.record(&mut Fields(&mut recorded));
+        for name in recorded.keys() {
+            state.happenings.push(format!("record {}", name));
+        }
+        state.fields.extend(recorded);
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = BTreeMap::new();
+        event.record(&mut Fields(&mut fields));
+        self.0
+            .lock()
+            .unwrap()
+            .happenings
+            .push(format!("event {}", fields["message"]));
+    }
+
+    fn enter(&self, _: &Id) {
+        self.0.lock().unwrap().happenings.push("enter".to_owned());
+    }
+
+    fn exit(&self, _: &Id) {
+        self.0.lock().unwrap().happenings.push("exit".to_owned());
+    }
+}
+
+#[test]
+fn span_covers_spawning_and_waiting() {
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), || {
+        Command::impure_parse("sh -c")
+            .with_arg("exit 3")
+            .run_and_wait()
+            .unwrap_err();
+    });
+    let state = recorder.0.lock().unwrap();
+    assert_eq!(state.fields["command"], "sh -c exit 3");
+    assert_eq!(
+        state.fields["cwd"],
+        format!("Some({:?})", std::env::current_dir().unwrap())
+    );
+    assert!(state.fields["pid"].parse::<u32>().unwrap() > 0);
+    assert_eq!(state.fields["exit_code"], "3");
+    assert!(state.fields["duration_ms"].parse::<u64>().is_ok());
+    assert_eq!(
+        state.happenings,
+        [
+            // Spawning
+            "enter",
+            "record pid",
+            "exit",
+            // Waiting
+            "enter",
+            "exit",
+            "record exit_code",
+            "record duration_ms",
+            "enter",
+            "event command exited",
+            "exit",
+        ]
+    );
+}
@@ -0,0 +1,38 @@
+#![cfg(unix)]
+
+use bossy::{Version, VersionProbe};
+use std::{env, fs, os::unix::fs::PermissionsExt as _};
+
+#[test]
+fn probing_ignores_dry_run() {
+    let _dry_run = bossy::scoped_dry_run(true);
+    let version = VersionProbe::new("sh")
+        .with_args(["-c", "echo 1.2.3"])
+        .probe()
+        .unwrap();
+    assert_eq!(version, Version::new(1, 2, 3));
+}
+
+#[test]
+fn cache_is_keyed_on_resolved_program() {
+    let root = env::temp_dir().join(format!("bossy-version-test-{}", std::process::id()));
+    let original_path = env::var_os("PATH").unwrap_or_default();
+    let mut found = Vec::new();
+    for version in ["1.0.0", "2.0.0"] {
+        let dir = root.join(version);
+        fs::create_dir_all(&dir).unwrap();
+        let tool = dir.join("bossy-test-tool");
+        fs::write(&tool, format!("#!/bin/sh\necho {}\n", version)).unwrap();
+        fs::set_permissions(&tool, fs::Permissions::from_mode(0o755)).unwrap();
+        let path =
+            env::join_paths(std::iter::once(dir).chain(env::split_paths(&original_path))).unwrap();
+        found.push(
+            VersionProbe::new("bossy-test-tool")
+                .with_env_var("PATH", path)
+                .probe()
+                .unwrap(),
+        );
+    }
+    fs::remove_dir_all(&root).unwrap();
+    assert_eq!(found, [Version::new(1, 0, 0), Version::new(2, 0, 0)]);
+}